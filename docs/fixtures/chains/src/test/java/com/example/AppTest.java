@@ -0,0 +1,7 @@
+package com.example;
+
+public class AppTest {
+    public void testMain() {
+        App.main(new String[] {});
+    }
+}
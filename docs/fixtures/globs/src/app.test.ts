@@ -0,0 +1,5 @@
+import { add } from "./app";
+
+test("adds numbers", () => {
+    expect(add(1, 2)).toBe(3);
+});
@@ -0,0 +1,6 @@
+#include "vector.h"
+
+template <typename T>
+std::size_t Vector<T>::size() const {
+    return length;
+}
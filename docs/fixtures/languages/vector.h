@@ -0,0 +1,13 @@
+#pragma once
+
+#include <cstddef>
+
+template <typename T>
+class Vector {
+  public:
+    std::size_t size() const;
+
+  private:
+    T *data;
+    std::size_t length;
+};
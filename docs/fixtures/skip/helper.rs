@@ -0,0 +1,2 @@
+// mdbook-files: skip
+fn helper() {}
@@ -0,0 +1,37 @@
+use crate::options::CheckCommand;
+use anyhow::{Context as _, Result};
+use log::*;
+use mdbook::{
+    preprocess::{Preprocessor, PreprocessorContext},
+    MDBook, MDBOOK_VERSION,
+};
+use mdbook_files::FilesPreprocessor;
+use serde_json::json;
+
+/// Renderer the book is checked for.
+const RENDERER: &str = "html";
+
+impl CheckCommand {
+    pub fn run(&self) -> Result<()> {
+        // checking only validates the widgets, it leaves the book source untouched
+        let preprocessor = FilesPreprocessor::new().dry_run();
+        let book = MDBook::load(&self.dir)
+            .with_context(|| format!("Loading book from {}", self.dir.display()))?;
+
+        // paths in the book are resolved against its root, so it needs to be absolute
+        let root = book.root.canonicalize()?;
+
+        // the context can only be constructed by deserializing it
+        let context: PreprocessorContext = serde_json::from_value(json!({
+            "root": root,
+            "config": book.config,
+            "renderer": RENDERER,
+            "mdbook_version": MDBOOK_VERSION,
+        }))?;
+
+        let chapters = book.book.iter().count();
+        preprocessor.run(&context, book.book)?;
+        info!("Checked {chapters} items, no problems found");
+        Ok(())
+    }
+}
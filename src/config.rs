@@ -0,0 +1,613 @@
+use crate::tree::FileEntry;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use toml::value::{Table, Value};
+
+/// Configuration for an invocation of files
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Files {
+    /// Path to files, or a list of paths whose files are merged into one tree.
+    ///
+    /// Relative paths are resolved against the prefix, absolute paths are used as they are.
+    pub path: Paths,
+
+    /// Prefix for `path`, which overrides the one given in the preprocessor configuration.
+    #[serde(default)]
+    pub prefix: Option<Utf8PathBuf>,
+
+    /// Explicit list of files to show, in order, instead of walking `path`.
+    ///
+    /// Files are relative to `path`, which must be a single path.
+    #[serde(default)]
+    pub list: Option<Vec<Utf8PathBuf>>,
+
+    /// Path that files are shown relative to, instead of `path`.
+    ///
+    /// This only affects how files are shown, it must be a parent of every file that is found.
+    /// Options that refer to files by their path, such as `default_file` and `ranges`, use the
+    /// path as shown, so they are relative to this when it is given.
+    #[serde(default)]
+    pub relative_to: Option<Utf8PathBuf>,
+
+    /// Caption shown above the widget.
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Add a glob to the set of overrides.
+    ///
+    /// Globs provided here have precisely the same semantics as a single line in a gitignore file,
+    /// where the meaning of `!` is inverted: namely, `!` at the beginning of a glob will ignore a
+    /// file. Without `!`, all matches of the glob provided are treated as whitelist matches.
+    #[serde(default)]
+    pub files: Vec<String>,
+
+    /// Globs of files to include. When given, only files matching one of them are shown.
+    ///
+    /// These narrow down the files matched by `files`, they never add files back.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Globs of files to exclude, even if they are matched by `include` or `files`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// When specified, path to the file that is opened by default, as it is shown in the tree.
+    ///
+    /// It must be one of the files shown, unless `strict` is disabled.
+    #[serde(default)]
+    pub default_file: Option<Utf8PathBuf>,
+
+    /// When specified, position of the file that is opened by default, in the order of the tree
+    /// and counting from zero. `default_file` takes precedence over this.
+    #[serde(default)]
+    pub default_index: Option<usize>,
+
+    /// Skip files which contain this marker near their start.
+    ///
+    /// Only the first few kilobytes of every file are searched for the marker.
+    #[serde(default)]
+    pub skip_marker: Option<String>,
+
+    /// Process ignores case insensitively
+    #[serde(default)]
+    pub ignore_case_insensitive: bool,
+
+    /// Do not cross file system boundaries.
+    ///
+    /// When this option is enabled, directory traversal will not descend into directories that are
+    /// on a different file system from the root path.
+    #[serde(default)]
+    pub same_file_system: bool,
+
+    /// Select the file type given by name.
+    #[serde(default)]
+    pub types: Vec<String>,
+
+    /// Exclude the file type given by name, even if it is selected by `types`.
+    #[serde(default)]
+    pub types_not: Vec<String>,
+
+    /// Enables ignoring hidden files.
+    #[serde(default)]
+    pub hidden: bool,
+
+    /// Whether to follow symbolic links or not.
+    ///
+    /// Links which point to one of their parent directories are skipped. Files which are found
+    /// through several links are only shown once, preferably under their own path.
+    #[serde(default)]
+    pub follow_links: bool,
+
+    /// Enables reading `.ignore` files.
+    ///
+    /// `.ignore` files have the same semantics as gitignore files and are supported by search
+    /// tools such as ripgrep and The Silver Searcher.
+    #[serde(default)]
+    pub dot_ignore: bool,
+
+    /// Name of additional ignore files to read, such as `.mdbook-files-ignore`.
+    ///
+    /// These have the same semantics as gitignore files and take precedence over all other ignore
+    /// files. The ignore files themselves are not shown.
+    #[serde(default)]
+    pub custom_ignore_filename: Option<String>,
+
+    /// Enables reading a global `gitignore` file, whose path is specified in git’s `core.excludesFile`
+    /// config option.
+    #[serde(default)]
+    pub git_global: bool,
+
+    /// Enables reading `.git/info/exclude` files.
+    #[serde(default)]
+    pub git_exclude: bool,
+
+    /// Enables reading `.gitignore` files.
+    #[serde(default)]
+    pub git_ignore: bool,
+
+    /// Whether a git repository is required to apply git-related ignore rules (global rules,
+    /// .gitignore and local exclude rules).
+    #[serde(default)]
+    pub require_git: bool,
+
+    /// Enables reading ignore files from parent directories.
+    #[serde(default)]
+    pub git_ignore_parents: bool,
+
+    /// The maximum depth to recurse.
+    ///
+    /// Depth is counted from the path that files are shown relative to, which is `path` unless
+    /// `relative_to` is given. A depth of 1 only shows the files directly inside of it.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Whether to ignore files above the specified limit.
+    #[serde(default)]
+    pub max_filesize: Option<u64>,
+
+    /// Order of entries within each directory of the tree.
+    #[serde(default)]
+    pub sort: Sort,
+
+    /// Paths of files or directories which are placed first in their directory, in this order.
+    ///
+    /// Directories containing one of these paths are placed first as well.
+    #[serde(default)]
+    pub priority: Vec<Utf8PathBuf>,
+
+    /// Render folders collapsed, except for the ones containing the visible file.
+    #[serde(default)]
+    pub collapsed: bool,
+
+    /// Show buttons above the tree which expand or collapse all folders.
+    #[serde(default)]
+    pub tree_controls: bool,
+
+    /// Leave out folders which do not contain any files.
+    #[serde(default = "default_exclude_empty_dirs")]
+    pub exclude_empty_dirs: bool,
+
+    /// Merge folders whose only entry is another folder into one, such as `com/example/foo`.
+    #[serde(default)]
+    pub collapse_chains: bool,
+
+    /// Show the directory as a folder at the root of the tree.
+    #[serde(default)]
+    pub root: bool,
+
+    /// Label of the root folder, defaults to the name of the directory. Implies `root`.
+    #[serde(default)]
+    pub root_label: Option<String>,
+
+    /// Show a header with the name of the directory above the tree, which downloads all files
+    /// when `download_zip` is enabled, and expands or collapses all folders otherwise.
+    #[serde(default)]
+    pub root_header: bool,
+
+    /// Show the size of each file in the tree.
+    #[serde(default)]
+    pub show_size: bool,
+
+    /// Show the date each file was last modified in the tree.
+    #[serde(default)]
+    pub show_modified: bool,
+
+    /// Mark executable files in the tree. This has no effect on platforms without permission
+    /// bits.
+    #[serde(default)]
+    pub show_mode: bool,
+
+    /// Show an icon for the type of each file and folder in the tree.
+    #[serde(default)]
+    pub icons: bool,
+
+    /// Map of file paths to labels shown next to them, such as `new` or `deprecated`.
+    ///
+    /// Paths are as shown in the tree, and each one must be a file that is shown.
+    #[serde(default)]
+    pub annotations: BTreeMap<Utf8PathBuf, String>,
+
+    /// URL that the path of each file is appended to, to link to it from the tree.
+    #[serde(default)]
+    pub link_base: Option<String>,
+
+    /// Show only the contents of a single file, without a list of files.
+    ///
+    /// Exactly one file must match. The widget is as high as the file unless `height` is given.
+    /// Options of the list of files, such as `download_zip` and `root_header`, cannot be used.
+    #[serde(default)]
+    pub single: bool,
+
+    /// Show all files in a single flat list, without folders.
+    #[serde(default)]
+    pub flatten: bool,
+
+    /// Names shown for files when the list is flattened.
+    #[serde(default)]
+    pub flatten_names: FlattenNames,
+
+    /// Load file contents when they are first shown, rather than embedding them in the page.
+    ///
+    /// The contents of every file are written out as separate assets into the book source.
+    #[serde(default)]
+    pub lazy: bool,
+
+    /// Color scheme of the widget, inherits the styling of the book when unset.
+    #[serde(default)]
+    pub theme: Option<Theme>,
+
+    /// Classes added to the widget, separated by spaces, for styling it differently.
+    #[serde(default)]
+    pub class: Option<String>,
+
+    /// Prefix of the ids of the elements of the widget, so that `files-…` becomes `{prefix}-files-…`.
+    #[serde(default)]
+    pub id_prefix: Option<String>,
+
+    /// Maximum number of files to show, exceeding it is an error.
+    #[serde(default)]
+    pub max_files: Option<usize>,
+
+    /// Height of the widget, as a number followed by `px`, `em`, `rem`, `vh` or `%`.
+    #[serde(default)]
+    pub height: Option<String>,
+
+    /// Syntax highlighting mode.
+    #[serde(default)]
+    pub highlight: Highlight,
+
+    /// Theme to use for syntect highlighting.
+    ///
+    /// Defaults to a light theme, or a dark one when `theme` is dark.
+    #[serde(default)]
+    pub highlight_theme: Option<String>,
+
+    /// Show the name of the language of each file above its contents.
+    #[serde(default)]
+    pub language_label: bool,
+
+    /// Show line numbers next to the file contents.
+    ///
+    /// Line numbers are rendered in a separate gutter next to the code block, so that they do not
+    /// interfere with syntax highlighting.
+    #[serde(default)]
+    pub line_numbers: bool,
+
+    /// Fail when a file cannot be read, or the default file does not exist.
+    ///
+    /// When disabled, files which cannot be read are rendered with an error message instead and
+    /// the first file is shown in place of a missing default file, so that a single bad file does
+    /// not break the whole widget.
+    #[serde(default = "default_strict")]
+    pub strict: bool,
+
+    /// Show a placeholder when no files match, rather than failing.
+    #[serde(default)]
+    pub allow_empty: bool,
+
+    /// Show a search box above the tree, which filters the entries by path.
+    #[serde(default)]
+    pub search: bool,
+
+    /// Map of file names, globs or extensions to the language used for highlighting them.
+    ///
+    /// File names take precedence over globs, which take precedence over extensions. When several
+    /// globs match the path of a file, the longest one wins. Files which are not matched by this
+    /// map and are not a well-known file such as `Makefile` are highlighted using their extension
+    /// as the language.
+    #[serde(default)]
+    pub languages: BTreeMap<String, String>,
+
+    /// Width of tabs in the file contents, in spaces. Uses the default of the browser when unset.
+    #[serde(default)]
+    pub tab_size: Option<u8>,
+
+    /// Wrap long lines in the file contents, rather than scrolling horizontally.
+    #[serde(default)]
+    pub wrap: bool,
+
+    /// Scroll the contents of each file within the widget, rather than letting long files grow
+    /// the pane.
+    #[serde(default)]
+    pub pane_scroll: bool,
+
+    /// Map of file paths to the ranges of lines that are shown for them.
+    ///
+    /// Paths are as shown in the tree, ranges are comma-separated lists of line numbers or inclusive
+    /// ranges of line numbers, such as `10-25,40`.
+    #[serde(default)]
+    pub ranges: BTreeMap<Utf8PathBuf, String>,
+
+    /// Map of file paths to lines which are highlighted in them.
+    ///
+    /// Paths are as shown in the tree, lines are given in the same format as `ranges`. They can
+    /// only be highlighted in the code itself with syntect highlighting, otherwise only the line
+    /// numbers are highlighted.
+    #[serde(default)]
+    pub highlight_lines: BTreeMap<Utf8PathBuf, String>,
+
+    /// Line shown in place of lines which are omitted by `ranges`.
+    #[serde(default)]
+    pub ellipsis: Option<String>,
+
+    /// Show a link to download all files as a zip archive.
+    ///
+    /// The archive is written as an asset into the book source. It contains the files as they are
+    /// on disk, unaffected by options such as `normalize_newlines`, `trim_end` or `ranges`.
+    #[serde(default)]
+    pub download_zip: bool,
+
+    /// Arrangement of the list of files and their contents.
+    #[serde(default)]
+    pub layout: Layout,
+
+    /// Render Markdown files, rather than showing their source.
+    #[serde(default)]
+    pub render_markdown: bool,
+
+    /// Remove leading whitespace which all lines of a file have in common.
+    #[serde(default)]
+    pub dedent: bool,
+
+    /// Maximum number of bytes shown of every file, the rest is cut off.
+    #[serde(default)]
+    pub max_display_bytes: Option<u64>,
+
+    /// Convert Windows line endings (CRLF) to Unix ones (LF).
+    #[serde(default)]
+    pub normalize_newlines: bool,
+
+    /// Remove trailing whitespace, such as blank lines, from the end of files.
+    #[serde(default)]
+    pub trim_end: bool,
+
+    /// Text appended to the info string of code blocks, after the language.
+    ///
+    /// This allows passing flags such as `,noplayground` on to other preprocessors.
+    #[serde(default)]
+    pub fence_suffix: Option<String>,
+
+    /// Show the path of the open file above its contents.
+    #[serde(default)]
+    pub breadcrumb: bool,
+
+    /// Add a line break after the widget.
+    #[serde(default = "default_trailing_break")]
+    pub trailing_break: bool,
+}
+
+fn default_strict() -> bool {
+    true
+}
+
+fn default_trailing_break() -> bool {
+    true
+}
+
+fn default_exclude_empty_dirs() -> bool {
+    true
+}
+
+/// One or more paths to show files of
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum Paths {
+    /// Single path.
+    Single(Utf8PathBuf),
+    /// List of paths, whose files are merged.
+    Multiple(Vec<Utf8PathBuf>),
+}
+
+impl Paths {
+    pub fn as_slice(&self) -> &[Utf8PathBuf] {
+        match self {
+            Paths::Single(path) => std::slice::from_ref(path),
+            Paths::Multiple(paths) => paths,
+        }
+    }
+}
+
+/// Syntax highlighting mode
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlight {
+    /// Emit fenced code blocks, which are highlighted by mdBook in the browser.
+    #[default]
+    Mdbook,
+
+    /// Highlight files using syntect when building the book, falling back to fenced code blocks
+    /// for files that it has no syntax for.
+    Syntect,
+}
+
+/// Color scheme of the widget
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub(crate) fn class(&self) -> &'static str {
+        match self {
+            Theme::Light => "mdbook-files-theme-light",
+            Theme::Dark => "mdbook-files-theme-dark",
+        }
+    }
+}
+
+/// Configuration for the plugin
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    /// Path that the paths of widgets are relative to, itself relative to the root of the book.
+    ///
+    /// Defaults to the source directory of the book.
+    #[serde(default)]
+    pub prefix: Option<Utf8PathBuf>,
+
+    /// Directory, relative to the book source, that generated assets are written into.
+    #[serde(default)]
+    pub assets: Option<Utf8PathBuf>,
+
+    /// Derive identifiers from paths rather than generating random ones, so that building the
+    /// same book twice produces identical output.
+    #[serde(default)]
+    pub deterministic: bool,
+
+    /// Info string of the code blocks which are turned into widgets.
+    #[serde(default = "default_label")]
+    pub label: String,
+
+    /// Allow widgets to show files outside of the prefix, such as through `..` or absolute paths.
+    #[serde(default)]
+    pub allow_outside: bool,
+
+    /// Path to a Tera template, relative to the root of the book, which replaces the script of
+    /// the widgets.
+    #[serde(default)]
+    pub script_template: Option<Utf8PathBuf>,
+
+    /// Height of widgets which do not set their own, as a number followed by a unit.
+    #[serde(default)]
+    pub default_height: Option<String>,
+
+    /// Options applied to every widget, which can be overridden by the widget itself.
+    #[serde(default)]
+    pub defaults: Table,
+}
+
+fn default_label() -> String {
+    "files".into()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prefix: None,
+            assets: None,
+            deterministic: false,
+            label: default_label(),
+            allow_outside: false,
+            script_template: None,
+            default_height: None,
+            defaults: Table::new(),
+        }
+    }
+}
+
+/// Merge the book-level defaults into the options of a widget.
+///
+/// Options of the widget take precedence. Tables, such as `languages`, are merged key by key,
+/// while any other value, including lists such as `files`, replaces the default entirely.
+pub(crate) fn merge_defaults(options: &mut Table, defaults: &Table) {
+    for (key, default) in defaults {
+        match (options.get_mut(key), default) {
+            (None, default) => {
+                options.insert(key.clone(), default.clone());
+            }
+            (Some(Value::Table(options)), Value::Table(default)) => {
+                merge_defaults(options, default);
+            }
+            (Some(_), _) => {}
+        }
+    }
+}
+
+/// Arrangement of the list of files and their contents
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Tree of files next to their contents.
+    #[default]
+    Tree,
+
+    /// Row of tabs, one per file, above their contents.
+    Tabs,
+
+    /// No list of files, only their contents and the tree as JSON, for scripts which render
+    /// their own list.
+    Json,
+}
+
+impl Layout {
+    /// Class applied to the widget for this layout.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Layout::Tree => "mdbook-files-layout-tree",
+            Layout::Tabs => "mdbook-files-layout-tabs",
+            Layout::Json => "mdbook-files-layout-json",
+        }
+    }
+}
+
+/// Names shown for files in a flattened list
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlattenNames {
+    /// Name of the file only. Files sharing a name are shown with their path instead.
+    #[default]
+    Basename,
+
+    /// Path of the file.
+    Path,
+}
+
+impl FlattenNames {
+    /// Name shown for a file.
+    pub fn name(self, entry: &FileEntry) -> &str {
+        match self {
+            FlattenNames::Basename => entry.path.file_name().unwrap_or(entry.path.as_str()),
+            FlattenNames::Path => entry.path.as_str(),
+        }
+    }
+}
+
+/// Order of entries within a directory
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    /// Sort entries by name.
+    #[default]
+    Name,
+
+    /// Sort entries by name, with directories before files.
+    NameDirsFirst,
+
+    /// Sort entries by extension, then by name. Directories come first.
+    Extension,
+
+    /// Sort entries by modification time, most recent first. Directories are ordered by their
+    /// most recently modified file.
+    Modified,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn merge_defaults_keeps_options() {
+        let mut options = table("path = \"src\"\nfiles = [\"*.rs\"]\nheight = \"20em\"");
+        let defaults = table("files = [\"*.md\", \"*.toml\"]\nhidden = true");
+        merge_defaults(&mut options, &defaults);
+        assert_eq!(
+            options,
+            table("path = \"src\"\nfiles = [\"*.rs\"]\nheight = \"20em\"\nhidden = true")
+        );
+    }
+
+    #[test]
+    fn merge_defaults_merges_tables() {
+        let mut options = table("[languages]\nrs = \"rust\"");
+        let defaults = table("[languages]\nrs = \"text\"\nh = \"c\"");
+        merge_defaults(&mut options, &defaults);
+        assert_eq!(options, table("[languages]\nrs = \"rust\"\nh = \"c\""));
+    }
+}
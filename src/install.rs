@@ -0,0 +1,80 @@
+use crate::options::InstallCommand;
+use anyhow::{Context as _, Result};
+use log::*;
+use std::{
+    fs,
+    path::{Component, Path},
+};
+use toml_edit::{value, Array, Document, Item, Table};
+
+/// Name of the stylesheet written into the assets directory.
+const STYLE_NAME: &str = "mdbook-files.css";
+
+/// Bundled stylesheet for the widget.
+const STYLE: &str = include_str!("../style.css");
+
+impl InstallCommand {
+    pub fn run(&self) -> Result<()> {
+        let config_path = self.dir.join("book.toml");
+        let config = fs::read_to_string(&config_path)
+            .with_context(|| format!("Reading {}", config_path.display()))?;
+        let mut document: Document = config
+            .parse()
+            .with_context(|| format!("Parsing {}", config_path.display()))?;
+
+        let assets = self.assets.as_deref().unwrap_or(Path::new("theme"));
+        let style_path = assets.join(STYLE_NAME);
+        fs::create_dir_all(self.dir.join(assets))?;
+        info!("Writing {}", style_path.display());
+        fs::write(self.dir.join(&style_path), STYLE)?;
+
+        let style = style_path
+            .components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Self::update(&mut document, &style)?;
+
+        let output = document.to_string();
+        if output != config {
+            info!("Updating {}", config_path.display());
+            fs::write(&config_path, output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the preprocessor and stylesheet to the book configuration, unless already present.
+    fn update(document: &mut Document, style: &str) -> Result<()> {
+        let preprocessors = implicit_table(document.as_table_mut(), "preprocessor")?;
+        if !preprocessors.contains_key("files") {
+            // widgets are relative to the book source unless a prefix is given
+            preprocessors.insert("files", Item::Table(Table::new()));
+        }
+
+        let output = implicit_table(document.as_table_mut(), "output")?;
+        let html = implicit_table(output, "html")?;
+        let css = html
+            .entry("additional-css")
+            .or_insert_with(|| value(Array::new()))
+            .as_array_mut()
+            .context("additional-css must be an array")?;
+        if !css.iter().any(|item| item.as_str() == Some(style)) {
+            css.push(style);
+        }
+
+        Ok(())
+    }
+}
+
+/// Get a table by key, creating it as an implicit table if it does not exist yet.
+fn implicit_table<'a>(table: &'a mut Table, key: &str) -> Result<&'a mut Table> {
+    let item = table.entry(key).or_insert_with(|| {
+        let mut table = Table::new();
+        table.set_implicit(true);
+        Item::Table(table)
+    });
+    item.as_table_mut()
+        .with_context(|| format!("{key} must be a table"))
+}
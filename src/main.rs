@@ -5,6 +5,8 @@ use mdbook_files::FilesPreprocessor;
 use options::{Command, Options};
 use std::io;
 
+mod check;
+mod install;
 mod options;
 
 impl Options {
@@ -23,7 +25,8 @@ impl Options {
                 serde_json::to_writer(io::stdout(), &output)?;
                 Ok(())
             }
-            Some(Command::Install(_command)) => Ok(()),
+            Some(Command::Install(command)) => command.run(),
+            Some(Command::Check(command)) => command.run(),
         }
     }
 }
@@ -18,6 +18,8 @@ pub enum Command {
     Process,
     /// Install support for mdbook-files into the current mdbook project.
     Install(InstallCommand),
+    /// Run over a book without building it, failing if any widget has problems.
+    Check(CheckCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -25,8 +27,20 @@ pub struct SupportsCommand {
     pub renderer: String,
 }
 
+#[derive(Parser, Debug)]
+pub struct CheckCommand {
+    /// Root directory of the book.
+    #[clap(default_value = ".")]
+    pub dir: PathBuf,
+}
+
 #[derive(Parser, Debug)]
 pub struct InstallCommand {
+    /// Directory, relative to the book, to write the assets into (defaults to `theme`).
     #[clap(long)]
     pub assets: Option<PathBuf>,
+
+    /// Root directory of the book.
+    #[clap(default_value = ".")]
+    pub dir: PathBuf,
 }
@@ -0,0 +1,1370 @@
+use crate::{
+    config::{merge_defaults, Config},
+    render::{validate_height, Instance, DEFAULT_HEIGHT},
+    tree::FilesMap,
+    walk::ListingKey,
+};
+use anyhow::{bail, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use log::*;
+use mdbook::{
+    book::{Book, Chapter},
+    errors::Result as MdbookResult,
+    preprocess::{Preprocessor, PreprocessorContext},
+    BookItem,
+};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use pulldown_cmark_to_cmark::cmark;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
+    path::Path,
+    sync::Mutex,
+};
+use tera::Tera;
+use toml::value::{Table, Value};
+use uuid::Uuid;
+
+/// Namespace for deterministic identifiers.
+const NAMESPACE: Uuid = Uuid::from_u128(0x13077eed_c494_46c3_8f44_a3c248be5466);
+
+/// Default directory for generated assets, relative to the book source.
+const DEFAULT_ASSETS: &str = "mdbook-files";
+
+/// Name of the manifest listing the generated assets, within the assets directory.
+const MANIFEST: &str = "manifest.json";
+
+/// Manifest of the assets generated while building a book
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Paths of the generated assets, relative to the book source.
+    pub assets: BTreeSet<Utf8PathBuf>,
+}
+
+#[derive(Clone, Debug, Copy)]
+pub struct Context<'a> {
+    pub(crate) root: &'a Utf8Path,
+    pub(crate) prefix: &'a Utf8PathBuf,
+    pub(crate) tera: &'a Tera,
+    source: &'a Utf8Path,
+    assets: &'a Utf8Path,
+    pub(crate) deterministic: bool,
+    pub(crate) allow_outside: bool,
+    pub(crate) default_height: &'a str,
+    label: &'a str,
+    defaults: &'a Table,
+    pub(crate) listings: &'a Mutex<BTreeMap<ListingKey, FilesMap>>,
+    generated: &'a Mutex<BTreeSet<Utf8PathBuf>>,
+    dry_run: bool,
+}
+
+impl<'b> Context<'b> {
+    /// Run a function with a context that is not part of a book, for walking files without
+    /// rendering anything.
+    pub(crate) fn detached<T>(prefix: &Utf8Path, f: impl FnOnce(Context<'_>) -> T) -> T {
+        let prefix = prefix.to_path_buf();
+        let listings = Mutex::default();
+        let generated = Mutex::default();
+        f(Context {
+            root: &prefix,
+            prefix: &prefix,
+            tera: &Tera::default(),
+            source: &prefix,
+            assets: Utf8Path::new(""),
+            deterministic: true,
+            allow_outside: false,
+            default_height: DEFAULT_HEIGHT,
+            label: "files",
+            defaults: &Table::new(),
+            listings: &listings,
+            generated: &generated,
+            dry_run: true,
+        })
+    }
+
+    /// Write a generated asset into the book source, returning its path relative to the book.
+    ///
+    /// Assets are named by their contents and only written when they do not exist yet, so that
+    /// repeated builds do not cause `mdbook serve` to rebuild endlessly.
+    pub(crate) fn asset(&self, extension: &str, data: &[u8]) -> Result<Utf8PathBuf> {
+        let name = Uuid::new_v5(&Uuid::NAMESPACE_OID, data);
+        let path = self.assets.join(format!("{name}.{extension}"));
+        let target = self.source.join(&path);
+        if !self.dry_run && !target.exists() {
+            debug!("Writing asset {target}");
+            std::fs::create_dir_all(self.source.join(self.assets))?;
+            std::fs::write(&target, data)?;
+        }
+        self.generated.lock().unwrap().insert(path.clone());
+        Ok(path)
+    }
+
+    /// Write the manifest of generated assets, unless it is unchanged.
+    fn manifest(&self) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let manifest = Manifest {
+            assets: std::mem::take(&mut *self.generated.lock().unwrap()),
+        };
+        let target = self.source.join(self.assets).join(MANIFEST);
+        let existing = match std::fs::read(&target) {
+            Ok(data) => Some(serde_json::from_slice::<Manifest>(&data).ok()),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+        match existing {
+            Some(Some(existing)) if existing == manifest => return Ok(()),
+            None if manifest.assets.is_empty() => return Ok(()),
+            _ => {}
+        }
+        debug!("Writing manifest {target}");
+        std::fs::create_dir_all(self.source.join(self.assets))?;
+        std::fs::write(&target, serde_json::to_string_pretty(&manifest)?)?;
+        Ok(())
+    }
+
+    fn map(&self, book: Book) -> Result<Book> {
+        let mut book = book;
+        book.sections = std::mem::take(&mut book.sections)
+            .into_iter()
+            .map(|section| self.map_book_item(section))
+            .collect::<Result<_, _>>()?;
+        Ok(book)
+    }
+
+    fn map_book_item(&self, item: BookItem) -> Result<BookItem> {
+        let result = match item {
+            BookItem::Chapter(chapter) => BookItem::Chapter(self.map_chapter(chapter)?),
+            other => other,
+        };
+
+        Ok(result)
+    }
+
+    fn map_code(&self, code: &str, uuid: Uuid) -> Result<Vec<Event<'static>>> {
+        if code.trim().is_empty() {
+            bail!("Code block is empty, it needs at least a path");
+        }
+        let mut options: Table = toml::from_str(code).context("Invalid configuration")?;
+        merge_defaults(&mut options, self.defaults);
+        Instance {
+            data: Value::Table(options)
+                .try_into()
+                .context("Invalid configuration")?,
+            uuid,
+            context: *self,
+        }
+        .events()
+    }
+
+    /// Generate the identifier for a widget, which is derived from its position in the book if
+    /// identifiers are deterministic.
+    fn instance_uuid(&self, chapter: Option<&Path>, index: usize) -> Uuid {
+        match self.deterministic {
+            true => {
+                let chapter = chapter
+                    .map(|path| path.to_string_lossy())
+                    .unwrap_or_default();
+                Uuid::new_v5(&NAMESPACE, format!("{chapter}:{index}").as_bytes())
+            }
+            false => Uuid::new_v4(),
+        }
+    }
+
+    fn label(&self) -> &str {
+        self.label
+    }
+
+    fn map_chapter(&self, mut chapter: Chapter) -> Result<Chapter> {
+        chapter.content = self.map_markdown(&chapter.content, chapter.path.as_deref())?;
+        chapter.sub_items = std::mem::take(&mut chapter.sub_items)
+            .into_iter()
+            .map(|item| self.map_book_item(item))
+            .collect::<Result<_, _>>()?;
+        Ok(chapter)
+    }
+
+    /// Replace the `files` blocks of a chapter with widgets.
+    ///
+    /// Only the chapter itself is processed. The contents of files shown in widgets are emitted
+    /// as code or HTML, and never passed through here again.
+    fn map_markdown(&self, markdown: &str, chapter: Option<&Path>) -> Result<String> {
+        let mut parser = Parser::new_ext(markdown, Options::all()).into_offset_iter();
+        let mut events = vec![];
+        let mut index = 0;
+
+        loop {
+            let next = parser.next();
+            match next {
+                None => break,
+                Some((Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(label))), range))
+                    if &*label == self.label() =>
+                {
+                    // the contents may be split into several text events
+                    let mut code = String::new();
+                    for (event, _) in parser.by_ref() {
+                        match event {
+                            Event::Text(text) => code.push_str(&text),
+                            Event::End(_) => break,
+                            other => bail!("Unexpected {other:?} in code block"),
+                        }
+                    }
+
+                    let uuid = self.instance_uuid(chapter, index);
+                    index += 1;
+                    let mapped = self.map_code(&code, uuid).with_context(|| {
+                        let chapter = chapter
+                            .map(|path| path.display().to_string())
+                            .unwrap_or_else(|| "book".into());
+                        let start = markdown[..range.start].matches('\n').count() + 1;
+                        let end = start + markdown[range].trim_end().matches('\n').count();
+                        format!("Mapping code block in {chapter}, lines {start}-{end}")
+                    })?;
+
+                    events.extend(mapped);
+                }
+                Some((event, _)) => events.push(event),
+            }
+        }
+
+        let mut buf = String::with_capacity(markdown.len());
+        let output = cmark(events.iter(), &mut buf).map(|_| buf)?;
+        Ok(output)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FilesPreprocessor {
+    templates: Tera,
+    config: Option<Config>,
+    dry_run: bool,
+}
+
+impl Default for FilesPreprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FilesPreprocessor {
+    pub fn new() -> Self {
+        let mut templates = Tera::default();
+        templates
+            .add_raw_template("script", include_str!("script.js.tera"))
+            .unwrap();
+        Self {
+            templates,
+            config: None,
+            dry_run: false,
+        }
+    }
+
+    /// Create a preprocessor with a configuration, which is used when the book has no
+    /// `[preprocessor.files]` section, such as when it is run as part of a custom pipeline.
+    ///
+    /// ```
+    /// use mdbook::preprocess::Preprocessor;
+    /// use mdbook_files::{Config, FilesPreprocessor};
+    ///
+    /// let config = Config {
+    ///     prefix: Some("examples".into()),
+    ///     deterministic: true,
+    ///     ..Default::default()
+    /// };
+    /// let preprocessor = FilesPreprocessor::with_config(config);
+    /// assert_eq!(preprocessor.name(), "files");
+    /// ```
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config: Some(config),
+            ..Self::new()
+        }
+    }
+
+    /// Do not write generated assets or their manifest into the book source, for checking a book
+    /// without building it. Widgets still link to the assets they would have written.
+    pub fn dry_run(self) -> Self {
+        Self {
+            dry_run: true,
+            ..self
+        }
+    }
+}
+
+impl Preprocessor for FilesPreprocessor {
+    fn name(&self) -> &str {
+        "files"
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> MdbookResult<Book> {
+        let config: Config = match (ctx.config.get_preprocessor(self.name()), &self.config) {
+            (Some(config), _) => Value::Table(config.clone())
+                .try_into()
+                .with_context(|| format!("Invalid [preprocessor.{}] section", self.name()))?,
+            (None, Some(config)) => config.clone(),
+            (None, None) => {
+                warn!(
+                    "Missing [preprocessor.{}] section, using defaults",
+                    self.name()
+                );
+                Config::default()
+            }
+        };
+        if config.label.trim().is_empty() {
+            bail!("Label must not be empty");
+        }
+        let default_height = config.default_height.as_deref().unwrap_or(DEFAULT_HEIGHT);
+        validate_height(default_height).context("Invalid default_height")?;
+        let root = Utf8PathBuf::try_from(ctx.root.clone())?;
+        let source = Utf8PathBuf::try_from(ctx.root.join(&ctx.config.book.src))?;
+        let prefix = match &config.prefix {
+            Some(prefix) => root.join(prefix),
+            None => source.clone(),
+        };
+        let assets = config
+            .assets
+            .as_deref()
+            .unwrap_or(Utf8Path::new(DEFAULT_ASSETS));
+        let mut templates = self.templates.clone();
+        if let Some(path) = &config.script_template {
+            let path = root.join(path);
+            let template = std::fs::read_to_string(&path)
+                .with_context(|| format!("Cannot read script template {path}"))?;
+            templates
+                .add_raw_template("script", &template)
+                .with_context(|| format!("Invalid script template {path}"))?;
+        }
+        let listings = Mutex::default();
+        let generated = Mutex::default();
+        let instance = Context {
+            root: &root,
+            prefix: &prefix,
+            tera: &templates,
+            source: &source,
+            assets,
+            deterministic: config.deterministic,
+            allow_outside: config.allow_outside,
+            default_height,
+            label: &config.label,
+            defaults: &config.defaults,
+            listings: &listings,
+            generated: &generated,
+            dry_run: self.dry_run,
+        };
+        let book = instance.map(book)?;
+        instance.manifest()?;
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// Map a single code block, with the repository as the prefix.
+    fn map_code(code: &str) -> Vec<Event<'static>> {
+        map_code_with(code, &Table::new())
+    }
+
+    /// Concatenate the HTML of a widget.
+    fn html(events: &[Event<'static>]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Html(html) => Some(html.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn map_code_with(code: &str, defaults: &Table) -> Vec<Event<'static>> {
+        try_map_code(code, defaults).unwrap()
+    }
+
+    fn try_map_code(code: &str, defaults: &Table) -> Result<Vec<Event<'static>>> {
+        let preprocessor = FilesPreprocessor::new();
+        let prefix = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let listings = Mutex::default();
+        let generated = Mutex::default();
+        let context = Context {
+            root: &prefix,
+            prefix: &prefix,
+            tera: &preprocessor.templates,
+            source: &prefix,
+            assets: Utf8Path::new("target/assets"),
+            deterministic: true,
+            allow_outside: false,
+            default_height: DEFAULT_HEIGHT,
+            label: "files",
+            defaults,
+            listings: &listings,
+            generated: &generated,
+            dry_run: false,
+        };
+        context.map_code(code, Uuid::nil())
+    }
+
+    #[test]
+    fn trailing_break_by_default() {
+        let events = map_code(r#"path = "docs/fixtures/examples""#);
+        assert_eq!(events.last(), Some(&Event::HardBreak));
+    }
+
+    #[test]
+    fn trailing_break_disabled() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            trailing_break = false
+            "#,
+        );
+        assert!(!events.contains(&Event::HardBreak));
+    }
+
+    #[test]
+    fn defaults_apply_to_widgets() {
+        let defaults = toml::from_str("trailing_break = false").unwrap();
+        let events = map_code_with(r#"path = "docs/fixtures/examples""#, &defaults);
+        assert!(!events.contains(&Event::HardBreak));
+    }
+
+    #[test]
+    fn widgets_override_defaults() {
+        let defaults = toml::from_str("trailing_break = false").unwrap();
+        let events = map_code_with(
+            r#"
+            path = "docs/fixtures/examples"
+            trailing_break = true
+            "#,
+            &defaults,
+        );
+        assert_eq!(events.last(), Some(&Event::HardBreak));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_links_skips_loops_and_aliases() {
+        use std::os::unix::fs::symlink;
+
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/symlinks");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("README.md"), "# Symlinks\n").unwrap();
+        symlink("..", dir.join("sub/up")).unwrap();
+        symlink("README.md", dir.join("alias.md")).unwrap();
+
+        let events = map_code(
+            r#"
+            path = "target/tests/symlinks"
+            follow_links = true
+            "#,
+        );
+        let html = html(&events);
+        assert_eq!(html.matches("data-path=").count(), 1);
+        assert!(html.contains(r#"data-path="README.md""#));
+    }
+
+    // names with markup cannot be checked out on Windows, so they are created by the test
+    #[cfg(unix)]
+    #[test]
+    fn markup_in_names_is_escaped() {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/markup");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("<i>folder")).unwrap();
+        std::fs::write(dir.join("<i>folder/file.txt"), "Folder with markup\n").unwrap();
+        std::fs::write(dir.join("a<b>c.txt"), "File with markup\n").unwrap();
+
+        let html = html(&map_code(r#"path = "target/tests/markup""#));
+        assert!(html.contains(">&lt;i&gt;folder/<"), "{html}");
+        assert!(html.contains(">a&lt;b&gt;c.txt<"), "{html}");
+        assert!(!html.contains("<i>"), "{html}");
+        assert!(!html.contains("<b>"), "{html}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quotes_in_attributes_are_escaped() {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/quotes");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("say \"hi\".txt"), "File with quotes\n").unwrap();
+
+        let html = html(&map_code(r#"path = "target/tests/quotes""#));
+        assert!(
+            html.contains(r#"data-path="say &quot;hi&quot;.txt""#),
+            "{html}"
+        );
+        assert!(html.contains(r#"title="say &quot;hi&quot;.txt""#), "{html}");
+        assert!(!html.contains(r#""hi""#), "{html}");
+    }
+
+    // reading the memory of a process from its start fails regardless of permissions
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unreadable_file_is_reported() {
+        use std::os::unix::fs::symlink;
+
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/unreadable");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("readable.txt"), "Readable\n").unwrap();
+        symlink("/proc/self/mem", dir.join("unreadable.txt")).unwrap();
+        assert!(std::fs::read(dir.join("unreadable.txt")).is_err());
+
+        let html = html(&map_code(
+            r#"
+            path = "target/tests/unreadable"
+            follow_links = true
+            strict = false
+            "#,
+        ));
+        assert!(html.contains(r#"data-path="unreadable.txt""#), "{html}");
+        assert!(html.contains(r#"<p class="mdbook-files-error">could not read file: "#));
+        assert!(html.contains(r#"data-path="readable.txt""#), "{html}");
+
+        let code = "path = \"target/tests/unreadable\"\nfollow_links = true";
+        let error = format!("{:#}", try_map_code(code, &Table::new()).unwrap_err());
+        assert!(error.contains("unreadable.txt"), "{error}");
+    }
+
+    #[test]
+    fn invalid_options_fail_without_strict() {
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            strict = false
+            highlight = "syntect"
+            highlight_theme = "missing"
+            "#,
+            &Table::new(),
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Unknown highlight theme missing"), "{error}");
+
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            strict = false
+            languages = { "[.rs" = "rust" }
+            "#,
+            &Table::new(),
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Invalid glob"), "{error}");
+    }
+
+    #[test]
+    fn dark_theme_uses_dark_syntect_theme() {
+        let background = |code: &str| {
+            let html = html(&map_code(code));
+            let start = html.find("background-color: #").unwrap() + 19;
+            html[start..start + 6].to_string()
+        };
+        let light = background(
+            r#"
+            path = "docs/fixtures/examples"
+            highlight = "syntect"
+            "#,
+        );
+        assert_eq!(light, "ffffff");
+        let dark = background(
+            r#"
+            path = "docs/fixtures/examples"
+            highlight = "syntect"
+            theme = "dark"
+            "#,
+        );
+        assert_eq!(dark, "2b303b");
+        let explicit = background(
+            r#"
+            path = "docs/fixtures/examples"
+            highlight = "syntect"
+            highlight_theme = "InspiredGitHub"
+            theme = "dark"
+            "#,
+        );
+        assert_eq!(explicit, "ffffff");
+    }
+
+    #[test]
+    fn custom_class() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            class = "wide compact_2"
+            "#,
+        );
+        let Some(Event::Html(html)) = events.first() else {
+            panic!("Widget does not start with HTML");
+        };
+        assert!(html.contains(r#"class="mdbook-files mdbook-files-layout-tree wide compact_2""#));
+    }
+
+    #[test]
+    fn custom_class_rejects_quotes() {
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            class = 'wide" onclick="alert(1)'
+            "#,
+            &Table::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    /// Run the preprocessor on a chapter of the documentation book, with the given options.
+    fn run_book(options: serde_json::Value, content: &str) -> Result<String> {
+        let config = serde_json::json!({
+            "book": { "src": "src" },
+            "preprocessor": { "files": options },
+        });
+        run_book_with(&FilesPreprocessor::new(), config, content)
+    }
+
+    /// Run a preprocessor on a chapter of the documentation book, with the given book config.
+    fn run_book_with(
+        preprocessor: &FilesPreprocessor,
+        config: serde_json::Value,
+        content: &str,
+    ) -> Result<String> {
+        let root = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs");
+        let context: PreprocessorContext = serde_json::from_value(serde_json::json!({
+            "root": root,
+            "config": config,
+            "renderer": "html",
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        }))?;
+        let mut book = Book::new();
+        book.push_item(Chapter::new("Test", content.into(), "test.md", vec![]));
+        let book = preprocessor.run(&context, book)?;
+        match book.sections.into_iter().next() {
+            Some(BookItem::Chapter(chapter)) => Ok(chapter.content),
+            _ => bail!("Chapter is missing"),
+        }
+    }
+
+    #[test]
+    fn prefix_defaults_to_source() {
+        let content = run_book(
+            serde_json::json!({ "deterministic": true }),
+            "```files\npath = \"tests\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains(r#"data-path="breadcrumb.md""#));
+    }
+
+    #[test]
+    fn prefix_is_relative_to_root() {
+        let options = serde_json::json!({ "deterministic": true, "prefix": ".." });
+        let content = run_book(
+            options.clone(),
+            "```files\npath = \"docs/fixtures/flatten\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains(r#"data-path="src/util/mod.rs""#));
+        assert!(run_book(options, "```files\npath = \"tests\"\n```\n").is_err());
+    }
+
+    #[test]
+    fn widget_prefix_is_relative_to_root() {
+        let content = run_book(
+            serde_json::json!({ "deterministic": true, "prefix": "." }),
+            "```files\nprefix = \"fixtures\"\npath = \"examples\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains(r#"data-path="hello.rs""#));
+    }
+
+    #[test]
+    fn deterministic_output() {
+        let options = serde_json::json!({ "deterministic": true, "prefix": ".." });
+        let content = "```files\npath = \"docs/fixtures/flatten\"\n```\n\n\
+            ```files\npath = \"docs/fixtures/examples\"\nlayout = \"tabs\"\n```\n";
+        let first = run_book(options.clone(), content).unwrap();
+        let second = run_book(options, content).unwrap();
+        assert_eq!(first.as_bytes(), second.as_bytes());
+    }
+
+    #[test]
+    fn invalid_configuration_names_chapter() {
+        let content = "# Chapter\n\n```files\npath = \n```\n";
+        let result = run_book(serde_json::json!({ "prefix": ".." }), content);
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("test.md"), "{error}");
+        assert!(error.contains("lines 3-5"), "{error}");
+        assert!(error.contains("Invalid configuration"), "{error}");
+    }
+
+    #[test]
+    fn paths_outside_prefix_are_rejected() {
+        let result = try_map_code(r#"path = "../..""#, &Table::new());
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("outside of the prefix"), "{error}");
+    }
+
+    #[test]
+    fn allow_outside_permits_paths_outside_prefix() {
+        // the prefix defaults to the source of the book, so this is the repository
+        let code = "```files\npath = \"../..\"\nmax_depth = 1\n```\n";
+        let result = run_book(serde_json::json!({ "deterministic": true }), code);
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("outside of the prefix"), "{error}");
+
+        let options = serde_json::json!({ "deterministic": true, "allow_outside": true });
+        let content = run_book(options, code).unwrap();
+        assert!(content.contains(r#"data-path="Cargo.toml""#));
+    }
+
+    #[test]
+    fn absolute_paths() {
+        let inside = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/fixtures/examples");
+        let events = map_code(&format!("path = {:?}", inside.as_str()));
+        assert_eq!(data_paths(&events), ["goodbye.rs", "hello.rs"]);
+
+        let outside = std::env::temp_dir().join("mdbook-files-absolute");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("outside.txt"), "Outside\n").unwrap();
+        let code = format!("path = {:?}", outside.to_str().unwrap());
+        let error = format!("{:#}", try_map_code(&code, &Table::new()).unwrap_err());
+        assert!(error.contains("outside of the prefix"), "{error}");
+    }
+
+    #[test]
+    fn sort_by_modified() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/modified");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("folder")).unwrap();
+        let now = SystemTime::now();
+        for (path, age) in [("alpha.txt", 3), ("beta.txt", 1), ("folder/gamma.txt", 2)] {
+            let file = std::fs::File::create(dir.join(path)).unwrap();
+            file.set_modified(now - Duration::from_secs(age * 3600))
+                .unwrap();
+        }
+
+        let events = map_code(
+            r#"
+            path = "target/tests/modified"
+            sort = "modified"
+            "#,
+        );
+        assert_eq!(
+            tree_paths(&events),
+            ["beta.txt", "folder/gamma.txt", "alpha.txt"]
+        );
+    }
+
+    #[test]
+    fn visible_file_is_first_in_tree() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            sort = "name-dirs-first"
+            "#,
+        );
+        assert_eq!(tree_paths(&events)[0], "src/util/mod.rs");
+
+        let html = html(&events);
+        let start = html.find(r#"id="button-"#).unwrap() + r#"id="button-"#.len();
+        let uuid = &html[start..start + html[start..].find('"').unwrap()];
+        assert!(html.contains(&format!(r#"visible(linked() || "{uuid}")"#)));
+        assert!(html.contains(&format!(
+            r#"id="file-{uuid}" class="mdbook-file visible" data-path="src/util/mod.rs""#
+        )));
+    }
+
+    #[test]
+    fn panes_keep_order_when_read_in_parallel() {
+        let events = map_code(r#"path = "docs/fixtures/flatten""#);
+        let paths = data_paths(&events);
+        assert_eq!(paths, tree_paths(&events));
+        assert_eq!(paths.len(), 4);
+
+        // the contents following the start of each pane are those of its file
+        let mut panes: Vec<(String, String)> = vec![];
+        for event in &events {
+            match event {
+                Event::Html(html) if html.contains("data-path=") => {
+                    let path = data_paths(std::slice::from_ref(event)).remove(0);
+                    panes.push((path, String::new()));
+                }
+                Event::Text(text) => panes.last_mut().unwrap().1.push_str(text),
+                _ => {}
+            }
+        }
+        let root = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/fixtures/flatten");
+        for (path, contents) in &panes {
+            let expected = std::fs::read_to_string(root.join(path)).unwrap();
+            assert_eq!(contents, &expected, "{path}");
+        }
+        assert_eq!(panes.len(), paths.len());
+    }
+
+    #[test]
+    fn trim_end_of_blank_file() {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/blank-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("blank.txt"), " \n\n\t\n").unwrap();
+        let events = map_code(
+            r#"
+            path = "target/tests/blank-file"
+            trim_end = true
+            line_numbers = true
+            "#,
+        );
+        assert!(html(&events).contains(r#"<pre class="mdbook-files-gutter"></pre>"#));
+        assert!(events
+            .iter()
+            .all(|event| !matches!(event, Event::Text(text) if !text.is_empty())));
+    }
+
+    #[test]
+    fn empty_code_block() {
+        let options = serde_json::json!({ "prefix": ".." });
+        for content in ["```files\n```\n", "```files\n  \n\n```\n"] {
+            let error = format!("{:#}", run_book(options.clone(), content).unwrap_err());
+            assert!(error.contains("Code block is empty"), "{error}");
+        }
+    }
+
+    #[test]
+    fn code_block_split_into_events() {
+        // inside of a block quote, every line of a code block is a separate text event
+        let content =
+            "> ```files\n> path = \"docs/fixtures/examples\"\n>\n> files = [\"hello.rs\"]\n> ```\n";
+        let texts = Parser::new_ext(content, Options::all())
+            .filter(|event| matches!(event, Event::Text(_)))
+            .count();
+        assert!(texts > 1);
+
+        let options = serde_json::json!({ "deterministic": true, "prefix": ".." });
+        let content = run_book(options, content).unwrap();
+        assert!(content.contains(r#"data-path="hello.rs""#));
+        assert!(!content.contains(r#"data-path="goodbye.rs""#));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn show_mode_marks_executables() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/modes"
+            show_mode = true
+            "#,
+        );
+        let html = html(&events);
+        assert_eq!(html.matches("mdbook-files-mode").count(), 1);
+        assert!(html.contains(r#"build.sh<span class="mdbook-files-mode""#));
+    }
+
+    #[test]
+    fn image_dimensions() {
+        let events = map_code(r#"path = "docs/fixtures/images""#);
+        let html = html(&events);
+        let images: Vec<_> = html.split("<img ").skip(1).collect();
+        assert_eq!(images.len(), 2);
+        // the dimensions of SVG images cannot be determined from their header
+        assert!(images
+            .iter()
+            .any(|image| image.contains(r#"width="16" height="16">"#)));
+        assert!(images.iter().any(|image| !image.contains("width=")));
+    }
+
+    #[test]
+    fn single_file_without_tree() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            list = ["hello.rs"]
+            single = true
+            "#,
+        );
+        let html = html(&events);
+        assert!(html.contains("mdbook-files-single"));
+        assert!(html.contains(r#"data-path="hello.rs""#));
+        assert!(!html.contains("<ul"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn single_requires_one_file() {
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            single = true
+            "#,
+            &Table::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn single_rejects_list_options() {
+        for option in ["download_zip", "root_header"] {
+            let code = format!(
+                "path = \"docs/fixtures/examples\"\nlist = [\"hello.rs\"]\nsingle = true\n{option} = true"
+            );
+            let error = format!("{:#}", try_map_code(&code, &Table::new()).unwrap_err());
+            assert!(error.contains(option), "{error}");
+        }
+    }
+
+    #[test]
+    fn custom_id_prefix() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            id_prefix = "docs"
+            "#,
+        );
+        let html = html(&events);
+        let uuid = Uuid::nil();
+        assert!(html.contains(&format!(r#"id="docs-files-{uuid}""#)));
+        assert!(html.contains(r#"id="docs-button-"#));
+        assert!(html.contains(r#"id="docs-file-"#));
+        assert!(html.contains(r#"const prefix = "docs-";"#));
+        assert!(!html.contains(r#"id="button-"#));
+        assert!(!html.contains(r#"id="file-"#));
+    }
+
+    #[test]
+    fn invalid_id_prefix() {
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            id_prefix = "1 docs"
+            "#,
+            &Table::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn types_not_excludes_images() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/images"
+            types_not = ["image"]
+            "#,
+        );
+        let html = html(&events);
+        assert!(html.contains(r#"data-path="notes.txt""#));
+        assert!(!html.contains(r#"data-path="gradient.png""#));
+        assert!(!html.contains(r#"data-path="circle.svg""#));
+        assert!(!html.contains("<img "));
+    }
+
+    #[test]
+    fn types_not_rejects_unknown_types() {
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/images"
+            types_not = ["pictures"]
+            "#,
+            &Table::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn missing_config_uses_defaults() {
+        let config = serde_json::json!({ "book": { "src": "src" } });
+        let content = run_book_with(
+            &FilesPreprocessor::new(),
+            config,
+            "```files\npath = \"tests\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains(r#"data-path="breadcrumb.md""#));
+    }
+
+    /// Write a script template for a test, returning its path.
+    fn script_template(name: &str, template: &str) -> Utf8PathBuf {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/templates");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, template).unwrap();
+        path
+    }
+
+    #[test]
+    fn custom_script_template() {
+        let path = script_template("count.js.tera", "console.log({{ count }});");
+        let content = run_book(
+            serde_json::json!({ "prefix": "..", "script_template": path }),
+            "```files\npath = \"docs/fixtures/examples\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains("<script>console.log(2);</script>"));
+    }
+
+    #[test]
+    fn invalid_script_template() {
+        let path = script_template("invalid.js.tera", "console.log({{ count );");
+        let result = run_book(
+            serde_json::json!({ "prefix": "..", "script_template": path }),
+            "```files\npath = \"docs/fixtures/examples\"\n```\n",
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Invalid script template"), "{error}");
+    }
+
+    #[test]
+    fn script_template_render_error() {
+        let path = script_template("missing.js.tera", "console.log({{ missing }});");
+        let result = run_book(
+            serde_json::json!({ "prefix": "..", "script_template": path }),
+            "```files\npath = \"docs/fixtures/examples\"\n```\n",
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("template \"script\""), "{error}");
+        assert!(error.contains("docs/fixtures/examples"), "{error}");
+        assert!(error.contains("missing"), "{error}");
+    }
+
+    #[test]
+    fn dry_run_writes_no_assets() {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/dry-run");
+        std::fs::create_dir_all(&dir).unwrap();
+        // unique contents, so that no other test has written the same asset
+        std::fs::write(dir.join("file.txt"), Uuid::new_v4().to_string()).unwrap();
+        let preprocessor = FilesPreprocessor::new().dry_run();
+        let config = serde_json::json!({
+            "book": { "src": "src" },
+            "preprocessor": { "files": { "prefix": "..", "deterministic": true } },
+        });
+        let content = run_book_with(
+            &preprocessor,
+            config,
+            "```files\npath = \"target/tests/dry-run\"\nlazy = true\n```\n",
+        )
+        .unwrap();
+        let start = content.find(r#"data-src=""#).unwrap() + r#"data-src=""#.len();
+        let end = start + content[start..].find('"').unwrap();
+        let asset = Utf8Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("docs/src")
+            .join(&content[start..end]);
+        assert!(asset.as_str().ends_with(".html"), "{asset}");
+        assert!(!asset.exists(), "{asset}");
+    }
+
+    #[test]
+    fn default_height() {
+        let code = "```files\npath = \"docs/fixtures/examples\"\n```\n";
+        let content = run_book(
+            serde_json::json!({ "prefix": "..", "default_height": "20em" }),
+            code,
+        )
+        .unwrap();
+        assert!(content.contains(r#"style="height: 20em;""#));
+
+        // the height of the widget takes precedence
+        let content = run_book(
+            serde_json::json!({ "prefix": "..", "default_height": "20em" }),
+            "```files\npath = \"docs/fixtures/examples\"\nheight = \"10vh\"\n```\n",
+        )
+        .unwrap();
+        assert!(content.contains(r#"style="height: 10vh;""#));
+
+        let result = run_book(
+            serde_json::json!({ "prefix": "..", "default_height": "20" }),
+            code,
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Invalid default_height"), "{error}");
+    }
+
+    #[test]
+    fn config_used_when_section_missing() {
+        let preprocessor = FilesPreprocessor::with_config(Config {
+            prefix: Some("..".into()),
+            deterministic: true,
+            ..Default::default()
+        });
+        let config = serde_json::json!({ "book": { "src": "src" } });
+        let code = "```files\npath = \"docs/fixtures/examples\"\n```\n";
+        let content = run_book_with(&preprocessor, config.clone(), code).unwrap();
+        assert!(content.contains(r#"data-path="hello.rs""#));
+
+        // the section of the book takes precedence
+        let config =
+            serde_json::json!({ "book": { "src": "src" }, "preprocessor": { "files": {} } });
+        assert!(run_book_with(&preprocessor, config, code).is_err());
+    }
+
+    #[test]
+    fn longest_language_glob_wins() {
+        // the shorter glob sorts first, so the longer one does not win by its position
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/globs"
+            languages = { "*.ts" = "typescript", "src/*.test.ts" = "javascript" }
+            "#,
+        );
+        let languages: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => Some(info.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(languages, ["javascript", "typescript"]);
+    }
+
+    #[test]
+    fn language_label() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            language_label = true
+            "#,
+        );
+        let html = html(&events);
+        assert_eq!(
+            html.matches(r#"<span class="mdbook-files-lang">Rust</span>"#)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn language_label_unknown() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/filenames"
+            files = ["Justfile"]
+            language_label = true
+            "#,
+        );
+        assert!(!html(&events).contains("mdbook-files-lang"));
+    }
+
+    #[test]
+    fn language_label_unknown_extension() {
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/unknown-language");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.foo"), "Unknown language\n").unwrap();
+        let events = map_code(
+            r#"
+            path = "target/tests/unknown-language"
+            language_label = true
+            "#,
+        );
+        assert!(!html(&events).contains("mdbook-files-lang"));
+    }
+
+    /// Paths of the files in the tree of a widget, in the order they are listed.
+    fn tree_paths(events: &[Event<'static>]) -> Vec<String> {
+        html(events)
+            .split(r#"class="mdbook-files-button""#)
+            .skip(1)
+            .map(|rest| {
+                let rest = &rest[rest.find(r#"title=""#).unwrap() + r#"title=""#.len()..];
+                rest[..rest.find('"').unwrap()].to_string()
+            })
+            .collect()
+    }
+
+    /// Paths of the files shown in a widget, in the order of their panes.
+    fn data_paths(events: &[Event<'static>]) -> Vec<String> {
+        html(events)
+            .split(r#"data-path=""#)
+            .skip(1)
+            .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn max_depth_top_level() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            max_depth = 1
+            "#,
+        );
+        assert_eq!(data_paths(&events), ["README.md"]);
+    }
+
+    #[test]
+    fn max_depth_nested() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            max_depth = 2
+            "#,
+        );
+        assert_eq!(
+            data_paths(&events),
+            ["README.md", "src/main.rs", "tests/main.rs"]
+        );
+    }
+
+    #[test]
+    fn max_depth_relative_to() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            relative_to = "docs/fixtures"
+            max_depth = 2
+            "#,
+        );
+        assert_eq!(data_paths(&events), ["flatten/README.md"]);
+    }
+
+    #[test]
+    fn keys_are_relative_to_relative_to() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            relative_to = "docs/fixtures"
+            default_file = "flatten/src/main.rs"
+            annotations = { "flatten/README.md" = "new" }
+            ranges = { "flatten/src/main.rs" = "1" }
+            highlight_lines = { "flatten/src/main.rs" = "1" }
+            "#,
+        );
+        let html = html(&events);
+        assert!(html.contains(r#"data-highlight-lines="1""#), "{html}");
+        assert!(html.contains(">new<"), "{html}");
+
+        for key in ["annotations", "ranges", "highlight_lines"] {
+            let code = format!(
+                r#"
+                path = "docs/fixtures/flatten"
+                relative_to = "docs/fixtures"
+                {key} = {{ "README.md" = "1" }}
+                "#
+            );
+            let error = format!("{:#}", try_map_code(&code, &Table::new()).unwrap_err());
+            assert!(error.contains("README.md, which is not one of"), "{error}");
+        }
+
+        let result = try_map_code(
+            r#"
+            path = "docs/fixtures/flatten"
+            relative_to = "docs/fixtures"
+            default_file = "README.md"
+            "#,
+            &Table::new(),
+        );
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(
+            error.contains("Default file README.md is not among"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn custom_ignore_filename() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/custom-ignore"
+            custom_ignore_filename = ".mdbook-files-ignore"
+            "#,
+        );
+        assert_eq!(data_paths(&events), ["shown.txt"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn non_utf8_paths_are_skipped() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("target/tests/non-utf8");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("valid.txt"), "Valid\n").unwrap();
+        let invalid = dir
+            .as_std_path()
+            .join(OsStr::from_bytes(b"invalid-\xff.txt"));
+        std::fs::write(invalid, "Invalid\n").unwrap();
+
+        let events = map_code(r#"path = "target/tests/non-utf8""#);
+        assert_eq!(data_paths(&events), ["valid.txt"]);
+    }
+
+    #[test]
+    fn root_header_toggles_folders() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            root_header = true
+            "#,
+        );
+        assert!(html(&events).contains(
+            r#"type="button" title="Expand or collapse all folders">examples/</button>"#
+        ));
+    }
+
+    #[test]
+    fn root_header_downloads_archive() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/examples"
+            root_header = true
+            download_zip = true
+            "#,
+        );
+        let html = html(&events);
+        assert!(html.contains(r#"<a class="mdbook-files-root" data-src="target/assets/"#));
+        assert!(html.contains(r#"download="examples.zip" title="Download all">examples/</a>"#));
+    }
+
+    #[test]
+    fn archive_keeps_original_bytes() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/whitespace"
+            normalize_newlines = true
+            trim_end = true
+            download_zip = true
+            "#,
+        );
+        let html = html(&events);
+        let start = html.find(r#"data-src="target/assets/"#).unwrap() + r#"data-src=""#.len();
+        let end = start + html[start..].find('"').unwrap();
+        let root = Utf8Path::new(env!("CARGO_MANIFEST_DIR"));
+        let archive = std::fs::File::open(root.join(&html[start..end])).unwrap();
+        let mut archive = zip::ZipArchive::new(archive).unwrap();
+        for name in ["crlf.txt", "trailing.txt"] {
+            let mut contents = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            let original = std::fs::read(root.join("docs/fixtures/whitespace").join(name)).unwrap();
+            assert_eq!(contents, original, "{name}");
+        }
+    }
+
+    #[test]
+    fn tree_controls() {
+        let code = r#"
+            path = "docs/fixtures/flatten"
+            tree_controls = true
+        "#;
+        let tree = html(&map_code(code));
+        assert!(tree.contains(r#"aria-label="Expand all folders""#));
+        assert!(tree.contains(r#"aria-label="Collapse all folders""#));
+
+        // tabs have no folders to expand
+        let tabs = html(&map_code(&format!("{code}\nlayout = \"tabs\"")));
+        assert!(!tabs.contains("mdbook-files-controls"));
+    }
+
+    #[test]
+    fn tab_size() {
+        let events = map_code(
+            r#"
+            path = "docs/fixtures/tabs"
+            tab_size = 2
+            "#,
+        );
+        assert!(html(&events).contains(r#"data-path="main.go" style="tab-size: 2;">"#));
+    }
+}
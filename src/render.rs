@@ -0,0 +1,1166 @@
+use crate::{
+    config::{self, Files, Highlight, Layout},
+    preprocessor::Context,
+    tree::{badge, FileEntry, FilesMap, TreeNode, TreeOptions},
+};
+use anyhow::{bail, Context as _, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::*;
+use pulldown_cmark::{escape::escape_html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use rayon::prelude::*;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write,
+    fs::File,
+    io::{Cursor, Read, Write as _},
+    ops::RangeInclusive,
+    sync::OnceLock,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, Theme, ThemeSet},
+    html::{styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+pub struct Instance<'a> {
+    pub(crate) context: Context<'a>,
+    pub(crate) data: Files,
+    pub(crate) uuid: Uuid,
+}
+
+/// Globs of the languages map of a widget, compiled once for all of its files.
+struct LanguageGlobs<'a> {
+    set: GlobSet,
+    /// Patterns and their languages, in the order of the set.
+    languages: Vec<(&'a str, &'a str)>,
+}
+
+/// Determine if a key of the languages map is a glob, rather than a file name or extension.
+fn is_glob(key: &str) -> bool {
+    key.contains(['*', '?', '[', '{', '/'])
+}
+
+/// Languages of well-known files which have no extension to go by.
+const FILENAME_LANGUAGES: &[(&str, &str)] = &[
+    ("Makefile", "makefile"),
+    ("makefile", "makefile"),
+    ("GNUmakefile", "makefile"),
+    ("Dockerfile", "dockerfile"),
+    ("Containerfile", "dockerfile"),
+    ("CMakeLists.txt", "cmake"),
+    ("Gemfile", "ruby"),
+    ("Rakefile", "ruby"),
+    ("Vagrantfile", "ruby"),
+    (".gitignore", "gitignore"),
+    (".dockerignore", "gitignore"),
+    (".bashrc", "bash"),
+    (".profile", "bash"),
+    (".zshrc", "bash"),
+];
+
+/// Default theme used for syntect highlighting.
+const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Default theme used for syntect highlighting in widgets with the dark color scheme.
+const DEFAULT_DARK_THEME: &str = "base16-ocean.dark";
+
+/// Syntax definitions used for syntect highlighting, loaded on first use.
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Name of a language as shown to the reader, such as `Rust` for `rs`, if it is known at all.
+fn language_name(language: &str) -> Option<&'static str> {
+    if language.is_empty() {
+        return None;
+    }
+    syntaxes()
+        .find_syntax_by_token(language)
+        .map(|syntax| syntax.name.as_str())
+}
+
+/// Themes used for syntect highlighting, loaded on first use.
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Units allowed for the height of the widget.
+const HEIGHT_UNITS: &[&str] = &["px", "em", "rem", "vh", "%"];
+
+/// Height of widgets, unless configured otherwise.
+pub(crate) const DEFAULT_HEIGHT: &str = "300px";
+
+/// Make sure that a height is a number followed by a unit, such as `300px`.
+pub(crate) fn validate_height(height: &str) -> Result<()> {
+    let split = height
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(height.len());
+    let (number, unit) = height.split_at(split);
+    if number.parse::<f64>().is_err() || !HEIGHT_UNITS.contains(&unit) {
+        bail!(
+            "Invalid height {height:?}, expected a number followed by one of {}",
+            HEIGHT_UNITS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Make sure that classes can be placed in an attribute as they are, and split them.
+fn split_classes(classes: &str) -> Result<Vec<&str>> {
+    let classes: Vec<_> = classes.split_whitespace().collect();
+    for class in &classes {
+        if !class
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid class {class:?}, only letters, digits, `-` and `_` are allowed");
+        }
+    }
+    Ok(classes)
+}
+
+/// Make sure that an id prefix can be used in attributes and selectors as it is.
+fn validate_id_prefix(prefix: &str) -> Result<()> {
+    let mut chars = prefix.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!("Invalid id_prefix {prefix:?}, it must start with a letter followed by letters, digits, `-` and `_`");
+    }
+    Ok(())
+}
+
+/// Parse a specification of line ranges, such as `10-25,40`.
+fn parse_ranges(spec: &str) -> Result<Vec<RangeInclusive<usize>>> {
+    let mut ranges = vec![];
+    for part in spec.split(',') {
+        let part = part.trim();
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse::<usize>())
+        else {
+            bail!("Invalid line range {part:?} in {spec:?}, expected a number or range");
+        };
+        if start == 0 || start > end {
+            bail!("Invalid line range {part:?} in {spec:?}, ranges must be ascending from 1");
+        }
+        ranges.push(start..=end);
+    }
+    Ok(ranges)
+}
+
+/// Determine if a line falls into any of the ranges.
+fn is_highlighted(ranges: &[RangeInclusive<usize>], number: usize) -> bool {
+    ranges.iter().any(|range| range.contains(&number))
+}
+
+/// Select the lines of a file which fall into any of the ranges.
+///
+/// Returns the selected contents along with the number of every line in them, which is `None` for
+/// the ellipsis inserted in place of omitted lines.
+fn select_lines(
+    contents: &str,
+    ranges: &[RangeInclusive<usize>],
+    ellipsis: Option<&str>,
+) -> (String, Vec<Option<usize>>) {
+    let mut output = String::new();
+    let mut numbers = vec![];
+    let mut omitted = false;
+    for (index, line) in contents.lines().enumerate() {
+        let number = index + 1;
+        if !ranges.iter().any(|range| range.contains(&number)) {
+            omitted = true;
+            continue;
+        }
+        if let (true, Some(ellipsis)) = (omitted, ellipsis) {
+            output.push_str(ellipsis);
+            output.push('\n');
+            numbers.push(None);
+        }
+        omitted = false;
+        output.push_str(line);
+        output.push('\n');
+        numbers.push(Some(number));
+    }
+    if let (true, Some(ellipsis)) = (omitted, ellipsis) {
+        output.push_str(ellipsis);
+        output.push('\n');
+        numbers.push(None);
+    }
+    (output, numbers)
+}
+
+/// Determine if a file is Markdown, by its extension.
+fn is_markdown(path: &Utf8Path) -> bool {
+    matches!(
+        path.extension().map(str::to_ascii_lowercase).as_deref(),
+        Some("md" | "markdown")
+    )
+}
+
+/// Make HTML safe to embed into Markdown as a single HTML block.
+///
+/// HTML blocks end at the first blank line, so newlines which would start a blank line are
+/// written as character references instead.
+fn html_block(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    for (index, line) in html.split('\n').enumerate() {
+        match (index, line.trim().is_empty()) {
+            (0, _) => {}
+            (_, true) => output.push_str("&#10;"),
+            (_, false) => output.push('\n'),
+        }
+        output.push_str(line);
+    }
+    output
+}
+
+/// Remove the leading whitespace which all non-blank lines have in common.
+///
+/// Whitespace is compared literally, so a tab is never treated as matching spaces. Lines without
+/// a number (ellipses inserted in place of omitted lines) are ignored and kept as they are.
+fn dedent(contents: &str, numbers: &[Option<usize>]) -> String {
+    let lines: Vec<_> = contents.lines().collect();
+    let mut indent: Option<&str> = None;
+    for (line, number) in lines.iter().zip(numbers) {
+        if number.is_none() || line.trim().is_empty() {
+            continue;
+        }
+        let whitespace = &line[..line.len() - line.trim_start().len()];
+        indent = Some(match indent {
+            None => whitespace,
+            Some(indent) => {
+                let common = indent
+                    .char_indices()
+                    .zip(whitespace.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map(|((index, _), _)| index)
+                    .unwrap_or(indent.len().min(whitespace.len()));
+                &indent[..common]
+            }
+        });
+    }
+
+    let indent = indent.unwrap_or("");
+    let mut output = String::with_capacity(contents.len());
+    for (line, number) in lines.iter().zip(numbers) {
+        let line = match number {
+            Some(_) if line.trim().is_empty() => "",
+            Some(_) => line.strip_prefix(indent).unwrap_or(line),
+            None => line,
+        };
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+/// Escape text for use in HTML.
+pub(crate) fn html_escape(text: &str) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    escape_html(&mut output, text)?;
+    Ok(output)
+}
+
+/// Escape text for use in an HTML attribute value.
+pub(crate) fn html_escape_attr(text: &str) -> Result<String> {
+    Ok(html_escape(text)?.replace('\'', "&#39;"))
+}
+
+/// Determine the MIME type of a file, if it is an image that can be shown inline.
+fn image_mime(path: &Utf8Path) -> Option<&'static str> {
+    let mime = match path.extension()?.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+impl<'a> Instance<'a> {
+    /// Generate the identifier for a file, which is derived from its path if identifiers are
+    /// deterministic.
+    pub(crate) fn file_uuid(&self, path: &Utf8Path) -> Uuid {
+        match self.context.deterministic {
+            true => Uuid::new_v5(&self.uuid, path.as_str().as_bytes()),
+            false => Uuid::new_v4(),
+        }
+    }
+
+    /// Prefix of the ids of the elements of this widget, including its separator.
+    fn id_prefix(&self) -> String {
+        match &self.data.id_prefix {
+            Some(prefix) => format!("{prefix}-"),
+            None => String::new(),
+        }
+    }
+
+    /// Identifier of the widget container, which the script is scoped to.
+    fn id(&self) -> String {
+        format!("{}files-{}", self.id_prefix(), self.uuid)
+    }
+
+    /// Prefix that paths of this widget are relative to. The prefix of the widget itself is
+    /// relative to the root of the book, like the one of the plugin.
+    pub(crate) fn prefix(&self) -> Utf8PathBuf {
+        match &self.data.prefix {
+            Some(prefix) => self.context.root.join(prefix),
+            None => self.context.prefix.clone(),
+        }
+    }
+
+    /// Directories that files are found in. Absolute paths bypass the prefix.
+    pub(crate) fn parents(&self) -> Vec<Utf8PathBuf> {
+        self.data
+            .path
+            .as_slice()
+            .iter()
+            .map(|path| match path.is_absolute() {
+                true => path.clone(),
+                false => self.prefix().join(path),
+            })
+            .collect()
+    }
+
+    fn tree(&self, files: &FilesMap) -> Result<TreeNode> {
+        let mut root = match self.data.flatten {
+            true => self.flat(files),
+            false => {
+                TreeNode::from_entries(files.values().map(|entry| (entry.path.as_path(), entry)))?
+            }
+        };
+
+        if self.data.exclude_empty_dirs {
+            root = root.prune_empty();
+        }
+
+        if self.data.collapse_chains {
+            root = root.collapse_chains();
+        }
+
+        if let Some(label) = self.root_label()? {
+            root = TreeNode::Directory([(label, root)].into());
+        }
+
+        Ok(root)
+    }
+
+    /// Tree without folders, with every file placed at the top.
+    fn flat(&self, files: &FilesMap) -> TreeNode {
+        let name = |entry| self.data.flatten_names.name(entry);
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for entry in files.values() {
+            *counts.entry(name(entry)).or_default() += 1;
+        }
+        let files = files
+            .values()
+            .map(|entry| {
+                let name = match counts[name(entry)] {
+                    1 => name(entry),
+                    _ => entry.path.as_str(),
+                };
+                (name.to_string(), TreeNode::File(entry.clone()))
+            })
+            .collect();
+        TreeNode::Directory(files)
+    }
+
+    /// Label of the root folder, if it should be shown.
+    fn root_label(&self) -> Result<Option<String>> {
+        if let Some(label) = &self.data.root_label {
+            return Ok(Some(label.clone()));
+        }
+        if !self.data.root {
+            return Ok(None);
+        }
+        let [path] = self.data.path.as_slice() else {
+            bail!("Showing the root of multiple paths requires a root_label");
+        };
+        Ok(Some(self.directory_name(path)?))
+    }
+
+    /// Name of a directory, as seen by the reader.
+    fn directory_name(&self, path: &Utf8Path) -> Result<String> {
+        let parent = self.prefix().join(path).canonicalize_utf8()?;
+        Ok(parent.file_name().unwrap_or(parent.as_str()).into())
+    }
+
+    fn tree_options(&self) -> TreeOptions {
+        TreeOptions {
+            sort: self.data.sort,
+            collapsed: self.data.collapsed,
+            show_size: self.data.show_size,
+            show_modified: self.data.show_modified,
+            show_mode: self.data.show_mode,
+            icons: self.data.icons,
+            annotations: self.data.annotations.clone(),
+            link_base: self.data.link_base.clone(),
+            id_prefix: self.id_prefix(),
+            priority: self.data.priority.clone(),
+        }
+    }
+
+    /// Build a zip archive of all files, preserving their relative paths.
+    ///
+    /// Files are archived as they are on disk, without the normalization and truncation that is
+    /// applied to the contents shown, so that downloads match the originals byte for byte.
+    fn archive(&self, files: &FilesMap) -> Result<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        // use a fixed modification time, so that the archive only changes when the files do
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default());
+        for (path, entry) in files {
+            writer.start_file(entry.path.as_str(), options)?;
+            writer.write_all(&std::fs::read(path)?)?;
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Name of all files together, used for the root header and the zip archive.
+    fn root_name(&self) -> Result<String> {
+        Ok(match (&self.data.root_label, self.data.path.as_slice()) {
+            (Some(label), _) => label.clone(),
+            (None, [path]) => self.directory_name(path)?,
+            (None, _) => "files".into(),
+        })
+    }
+
+    /// Write the zip archive of all files as an asset, returning its escaped URL.
+    fn archive_url(&self, files: &FilesMap) -> Result<String> {
+        let url = self.context.asset("zip", &self.archive(files)?)?;
+        html_escape_attr(url.as_str())
+    }
+
+    /// Render a link to download all files as a zip archive.
+    fn download(&self, url: &str) -> Result<String> {
+        let name = html_escape_attr(&format!("{}.zip", self.root_name()?))?;
+        Ok(format!(
+            r#"<a class="mdbook-files-download" data-src="{url}" download="{name}">Download all</a>"#
+        ))
+    }
+
+    /// Render the header above the list of files, which downloads all files if there is an
+    /// archive of them, and expands or collapses all folders otherwise.
+    fn root_header(&self, url: Option<&str>) -> Result<String> {
+        let name = self.root_name()?;
+        let label = html_escape(&format!("{name}/"))?;
+        Ok(match url {
+            Some(url) => {
+                let name = html_escape_attr(&format!("{name}.zip"))?;
+                format!(
+                    r#"<a class="mdbook-files-root" data-src="{url}" download="{name}" title="Download all">{label}</a>"#
+                )
+            }
+            None => format!(
+                r#"<button class="mdbook-files-root" type="button" title="Expand or collapse all folders">{label}</button>"#
+            ),
+        })
+    }
+
+    /// Render a row of tabs, one for every file in the tree.
+    fn tabs(&self, tree: &TreeNode) -> Result<String> {
+        let mut output = String::new();
+        output.push_str(r#"<div class="mdbook-files-tabs" role="tablist">"#);
+        let prefix = self.id_prefix();
+        for entry in tree.files(&self.tree_options()) {
+            let uuid = entry.uuid;
+            let path = html_escape(entry.path.as_str())?;
+            let title = html_escape_attr(entry.path.as_str())?;
+            let badge = match self.data.annotations.get(&entry.path) {
+                Some(label) => badge(label)?,
+                None => String::new(),
+            };
+            write!(
+                output,
+                r#"<button id="{prefix}button-{uuid}" class="mdbook-files-button" role="tab" aria-selected="false" title="{title}">{path}{badge}</button>"#
+            )?;
+        }
+        output.push_str("</div>");
+        Ok(output)
+    }
+
+    /// Render the tree as JSON, along with the identifiers of its files in the order they would
+    /// be listed in.
+    fn json(&self, tree: &TreeNode) -> Result<String> {
+        let order: Vec<Uuid> = tree
+            .files(&self.tree_options())
+            .iter()
+            .map(|entry| entry.uuid)
+            .collect();
+        let data = serde_json::to_string(&serde_json::json!({
+            "tree": tree,
+            "order": order,
+        }))?;
+        // `<` only occurs within strings, where escaping it keeps `</script>` from ending the element
+        let data = data.replace('<', "\\u003c");
+        Ok(format!(
+            r#"<script type="application/json" id="{}-data">{data}</script>"#,
+            self.id()
+        ))
+    }
+
+    fn left(&self, tree: &TreeNode, files: &FilesMap) -> Result<String> {
+        for path in self.data.annotations.keys() {
+            if !files.values().any(|entry| &entry.path == path) {
+                bail!("Annotation given for {path}, which is not one of the files shown");
+            }
+        }
+        for path in &self.data.priority {
+            if !files.values().any(|entry| entry.path.starts_with(path)) {
+                bail!("Priority given for {path}, which does not match any of the files shown");
+            }
+        }
+
+        let archive = match self.data.download_zip {
+            true => Some(self.archive_url(files)?),
+            false => None,
+        };
+
+        let mut output = String::new();
+        output.push_str(r#"<div class="mdbook-files-left">"#);
+        if self.data.root_header {
+            output.push_str(&self.root_header(archive.as_deref())?);
+        }
+        if self.data.search {
+            output.push_str(
+                r#"<input class="mdbook-files-search" type="search" placeholder="Search" aria-label="Search files">"#,
+            );
+        }
+        if self.data.tree_controls && self.data.layout == Layout::Tree {
+            output.push_str(concat!(
+                r#"<div class="mdbook-files-controls">"#,
+                r#"<button class="mdbook-files-expand-all" type="button" aria-label="Expand all folders">Expand all</button>"#,
+                r#"<button class="mdbook-files-collapse-all" type="button" aria-label="Collapse all folders">Collapse all</button>"#,
+                "</div>",
+            ));
+        }
+
+        let list = match self.data.layout {
+            Layout::Tree => tree.render(&self.tree_options())?,
+            Layout::Tabs => self.tabs(tree)?,
+            Layout::Json => self.json(tree)?,
+        };
+        output.push_str(&list);
+        if let Some(url) = &archive {
+            output.push_str(&self.download(url)?);
+        }
+        output.push_str("</div>");
+        Ok(output)
+    }
+
+    /// Render the opening of a line-numbered file, along with the gutter containing the numbers.
+    fn gutter(numbers: &[Option<usize>], highlight: &[RangeInclusive<usize>]) -> Result<String> {
+        let mut output = String::new();
+        output.push_str(r#"<div class="mdbook-files-lines"><pre class="mdbook-files-gutter">"#);
+        for number in numbers {
+            match number {
+                Some(number) if is_highlighted(highlight, *number) => writeln!(
+                    output,
+                    r#"<span class="mdbook-files-highlight-line">{number}</span>"#
+                )?,
+                Some(number) => writeln!(output, "{number}")?,
+                None => writeln!(output, "⋮")?,
+            }
+        }
+        output.push_str("</pre>");
+        Ok(output)
+    }
+
+    /// Theme used for syntect highlighting.
+    ///
+    /// Unless one is given, this follows the color scheme of the widget, as the background of
+    /// the theme is set on the code and would otherwise override it.
+    fn theme(&self) -> Result<&'static Theme> {
+        let name = match (&self.data.highlight_theme, self.data.theme) {
+            (Some(name), _) => name.as_str(),
+            (None, Some(config::Theme::Dark)) => DEFAULT_DARK_THEME,
+            (None, _) => DEFAULT_THEME,
+        };
+        let Some(theme) = themes().themes.get(name) else {
+            let known: Vec<_> = themes().themes.keys().map(String::as_str).collect();
+            bail!(
+                "Unknown highlight theme {name}, expected one of {}",
+                known.join(", ")
+            );
+        };
+        Ok(theme)
+    }
+
+    /// Render the contents of a text file as highlighted HTML using syntect.
+    fn syntect(
+        &self,
+        syntax: &SyntaxReference,
+        contents: &str,
+        numbers: &[Option<usize>],
+        highlight: &[RangeInclusive<usize>],
+    ) -> Result<String> {
+        let theme = self.theme()?;
+        let background = theme.settings.background.unwrap_or(Color::WHITE);
+        let mut output = format!(
+            r#"<pre class="mdbook-files-syntect"><code class="nohighlight" style="background-color: #{:02x}{:02x}{:02x};">"#,
+            background.r, background.g, background.b
+        );
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        for (line, number) in LinesWithEndings::from(contents).zip(numbers) {
+            let regions = highlighter.highlight_line(line, syntaxes())?;
+            let html = styled_line_to_highlighted_html(&regions, IncludeBackground::No)?;
+            match number {
+                Some(number) if is_highlighted(highlight, *number) => {
+                    let html = html.strip_suffix('\n').unwrap_or(&html);
+                    writeln!(
+                        output,
+                        r#"<span class="mdbook-files-highlight-line">{html}</span>"#
+                    )?;
+                }
+                _ => output.push_str(&html),
+            }
+        }
+        output.push_str("</code></pre>");
+        Ok(output)
+    }
+
+    /// Compile the globs of the languages map.
+    fn language_globs(&self) -> Result<LanguageGlobs<'_>> {
+        let mut set = GlobSetBuilder::new();
+        let mut languages = vec![];
+        for (pattern, language) in &self.data.languages {
+            if !is_glob(pattern) {
+                continue;
+            }
+            let glob = Glob::new(pattern)
+                .with_context(|| format!("Invalid glob {pattern:?} in languages"))?;
+            set.add(glob);
+            languages.push((pattern.as_str(), language.as_str()));
+        }
+        Ok(LanguageGlobs {
+            set: set.build()?,
+            languages,
+        })
+    }
+
+    /// Determine the highlighting language of a file from its path, name or extension.
+    ///
+    /// The path is relative, as shown in the tree, so that globs match against it.
+    fn language<'b>(&'b self, path: &'b Utf8Path, globs: &LanguageGlobs<'b>) -> &'b str {
+        let name = path.file_name().unwrap_or("");
+        let extension = path.extension().unwrap_or("");
+        if let Some(language) = self.data.languages.get(name) {
+            return language;
+        }
+
+        // the longest matching glob is the most specific one
+        let matched = globs
+            .set
+            .matches(path)
+            .into_iter()
+            .map(|index| globs.languages[index])
+            .min_by_key(|(pattern, _)| Reverse(pattern.len()));
+        if let Some((_, language)) = matched {
+            return language;
+        }
+
+        let builtin = FILENAME_LANGUAGES
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, language)| *language);
+        builtin
+            .or_else(|| self.data.languages.get(extension).map(String::as_str))
+            .unwrap_or(extension)
+    }
+
+    /// Render the contents of a text file as a code block.
+    ///
+    /// The path is relative, as shown in the tree.
+    fn code(
+        &self,
+        language: &str,
+        contents: String,
+        numbers: &[Option<usize>],
+        highlight: &[RangeInclusive<usize>],
+    ) -> Result<Vec<Event<'static>>> {
+        let mut events = vec![];
+        let info = match &self.data.fence_suffix {
+            Some(suffix) => format!("{language}{suffix}"),
+            None => language.to_string(),
+        };
+        let tag = Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Boxed(info.into())));
+
+        if self.data.line_numbers {
+            events.push(Event::Html(CowStr::Boxed(
+                Self::gutter(numbers, highlight)?.into_boxed_str(),
+            )));
+        }
+
+        let syntax = match self.data.highlight {
+            Highlight::Mdbook => None,
+            Highlight::Syntect => syntaxes().find_syntax_by_token(language),
+        };
+
+        match syntax {
+            Some(syntax) => {
+                let html = self.syntect(syntax, &contents, numbers, highlight)?;
+                events.push(Event::Html(CowStr::Boxed(html.into())));
+            }
+            None => {
+                events.push(Event::Start(tag.clone()));
+                events.push(Event::Text(CowStr::Boxed(contents.into())));
+                events.push(Event::End(tag));
+            }
+        }
+
+        if self.data.line_numbers {
+            events.push(Event::Html(CowStr::Boxed("</div>".to_string().into())));
+        }
+
+        Ok(events)
+    }
+
+    /// Render the contents of a single file pane, given its full path and its language.
+    fn pane(
+        &self,
+        path: &Utf8Path,
+        language: &str,
+        ranges: Option<&[RangeInclusive<usize>]>,
+        highlight: &[RangeInclusive<usize>],
+    ) -> Result<Vec<Event<'static>>> {
+        let (bytes, truncated) = self.read(path)?;
+
+        if let Some(mime) = image_mime(path) {
+            if let Some(size) = truncated {
+                return Ok(vec![Event::Html(CowStr::Boxed(
+                    format!(r#"<p class="mdbook-files-binary">image too large to show ({size} bytes)</p>"#)
+                        .into(),
+                ))]);
+            }
+            // intrinsic dimensions let the browser reserve space before the image is decoded
+            let dimensions = match imagesize::blob_size(&bytes) {
+                Ok(size) => format!(r#" width="{}" height="{}""#, size.width, size.height),
+                Err(error) => {
+                    debug!("Cannot determine dimensions of {path}: {error}");
+                    String::new()
+                }
+            };
+            let data = BASE64.encode(&bytes);
+            return Ok(vec![Event::Html(CowStr::Boxed(
+                format!(r#"<img src="data:{mime};base64,{data}"{dimensions}>"#).into(),
+            ))]);
+        }
+
+        let contents = match String::from_utf8(bytes) {
+            Ok(contents) => contents,
+            // truncating may have split the last character
+            Err(error) if truncated.is_some() && error.utf8_error().error_len().is_none() => {
+                let valid = error.utf8_error().valid_up_to();
+                let mut bytes = error.into_bytes();
+                bytes.truncate(valid);
+                String::from_utf8(bytes)?
+            }
+            Err(error) => {
+                let length = truncated.unwrap_or(error.as_bytes().len() as u64);
+                return Ok(vec![Event::Html(CowStr::Boxed(
+                    format!(r#"<p class="mdbook-files-binary">binary file ({length} bytes)</p>"#)
+                        .into(),
+                ))]);
+            }
+        };
+
+        let contents = match self.data.normalize_newlines {
+            true => contents.replace("\r\n", "\n"),
+            false => contents,
+        };
+        let contents = match (self.data.trim_end, contents.trim_end()) {
+            // a file of only whitespace has no lines left, rather than a single blank one
+            (true, "") => String::new(),
+            (true, trimmed) => format!("{trimmed}\n"),
+            (false, _) => contents,
+        };
+
+        let (contents, numbers) = match ranges {
+            Some(ranges) => select_lines(&contents, ranges, self.data.ellipsis.as_deref()),
+            None => {
+                let numbers = (1..=contents.lines().count()).map(Some).collect();
+                (contents, numbers)
+            }
+        };
+
+        let contents = match self.data.dedent {
+            true => dedent(&contents, &numbers),
+            false => contents,
+        };
+
+        let mut events = match self.data.render_markdown && is_markdown(path) {
+            true => vec![Event::Html(CowStr::Boxed(Self::markdown(&contents).into()))],
+            false => self.code(language, contents, &numbers, highlight)?,
+        };
+
+        if let Some(size) = truncated {
+            events.push(Event::Html(CowStr::Boxed(
+                format!(
+                    r#"<p class="mdbook-files-truncated">… (truncated, {size} bytes total)</p>"#
+                )
+                .into(),
+            )));
+        }
+
+        Ok(events)
+    }
+
+    /// Read a file, up to `max_display_bytes` of it.
+    ///
+    /// Returns the contents, along with the size of the file if it was truncated.
+    fn read(&self, path: &Utf8Path) -> Result<(Vec<u8>, Option<u64>)> {
+        let Some(limit) = self.data.max_display_bytes else {
+            return Ok((std::fs::read(path)?, None));
+        };
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut bytes = vec![];
+        file.take(limit).read_to_end(&mut bytes)?;
+        Ok((bytes, (size > limit).then_some(size)))
+    }
+
+    /// Render the contents of a Markdown file as HTML.
+    ///
+    /// This does not run the preprocessor on the contents, so `files` blocks in them are shown as
+    /// code blocks rather than expanded.
+    fn markdown(contents: &str) -> String {
+        let mut html = String::new();
+        html.push_str(r#"<div class="mdbook-files-markdown">"#);
+        pulldown_cmark::html::push_html(&mut html, Parser::new_ext(contents, Options::all()));
+        html.push_str("</div>");
+        html_block(&html)
+    }
+
+    /// File shown when the widget is loaded, given the files in the order of the tree.
+    fn visible<'b>(&self, entries: &[&'b FileEntry]) -> Result<&'b FileEntry> {
+        let visible = match &self.data.default_file {
+            Some(file) => match entries.iter().find(|entry| &entry.path == file) {
+                Some(entry) => entry,
+                None if self.data.strict => {
+                    let valid: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+                    bail!(
+                        "Default file {file} is not among the matched files, expected one of: {}",
+                        valid.join(", ")
+                    );
+                }
+                None => {
+                    warn!("Default file {file} is not among the matched files, using first file");
+                    &entries[0]
+                }
+            },
+            None => match self.data.default_index {
+                Some(index) => match entries.get(index) {
+                    Some(entry) => entry,
+                    None if self.data.strict => bail!(
+                        "Default index {index} is out of range, expected 0 to {}",
+                        entries.len() - 1
+                    ),
+                    None => {
+                        warn!("Default index {index} is out of range, using first file");
+                        &entries[0]
+                    }
+                },
+                None => &entries[0],
+            },
+        };
+        Ok(visible)
+    }
+
+    /// Render the path of a file as a breadcrumb, such as `src / main.rs`.
+    fn breadcrumb(path: &Utf8Path) -> Result<String> {
+        let parts = path
+            .components()
+            .map(|component| html_escape(component.as_str()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(format!(
+            r#"<div class="mdbook-files-breadcrumb">{}</div>"#,
+            parts.join(" / ")
+        ))
+    }
+
+    fn right(&self, files: &FilesMap, visible: &FileEntry) -> Result<Vec<Event<'static>>> {
+        let mut events = vec![];
+        events.push(Event::Html(CowStr::Boxed(
+            r#"<div class="mdbook-files-right">"#.to_string().into(),
+        )));
+        if self.data.breadcrumb {
+            events.push(Event::Html(CowStr::Boxed(
+                Self::breadcrumb(&visible.path)?.into(),
+            )));
+        }
+
+        let mut ranges = BTreeMap::new();
+        for (path, spec) in &self.data.ranges {
+            if !files.values().any(|entry| &entry.path == path) {
+                bail!("Line ranges given for {path}, which is not one of the files shown");
+            }
+            ranges.insert(path, parse_ranges(spec)?);
+        }
+
+        let mut highlights = BTreeMap::new();
+        for (path, spec) in &self.data.highlight_lines {
+            if !files.values().any(|entry| &entry.path == path) {
+                bail!("Highlighted lines given for {path}, which is not one of the files shown");
+            }
+            highlights.insert(path, parse_ranges(spec)?);
+        }
+
+        // fail on invalid options here, rather than reporting them as files which cannot be read
+        let globs = self.language_globs()?;
+        if self.data.highlight == Highlight::Syntect {
+            self.theme()?;
+        }
+
+        let prefix = self.id_prefix();
+        let mut class = String::from("mdbook-file");
+        if self.data.wrap {
+            class.push_str(" mdbook-files-wrap");
+        }
+        if self.data.pane_scroll {
+            class.push_str(" mdbook-files-scroll");
+        }
+
+        // read and render files in parallel, collecting keeps them in order
+        let files: Vec<_> = files.iter().collect();
+        let panes: Vec<_> = files
+            .par_iter()
+            .map(|(path, entry)| {
+                info!("Reading {path}");
+                let highlight = highlights.get(&entry.path).map(Vec::as_slice);
+                self.pane(
+                    path,
+                    self.language(&entry.path, &globs),
+                    ranges.get(&entry.path).map(Vec::as_slice),
+                    highlight.unwrap_or_default(),
+                )
+            })
+            .collect();
+
+        for ((path, entry), pane) in files.into_iter().zip(panes) {
+            let uuid = entry.uuid;
+            let mut attributes =
+                format!(r#"data-path="{}""#, html_escape_attr(entry.path.as_str())?);
+            if let Some(spec) = self.data.highlight_lines.get(&entry.path) {
+                write!(
+                    attributes,
+                    r#" data-highlight-lines="{}""#,
+                    html_escape_attr(spec)?
+                )?;
+            }
+            if let Some(size) = self.data.tab_size {
+                write!(attributes, r#" style="tab-size: {size};""#)?;
+            }
+            let mut pane = match pane {
+                Ok(pane) => pane,
+                Err(error) if !self.data.strict => {
+                    warn!("Error reading {path}: {error:#}");
+                    let error = html_escape(&format!("{error:#}"))?;
+                    vec![Event::Html(CowStr::Boxed(
+                        format!(
+                            r#"<p class="mdbook-files-error">could not read file: {error}</p>"#
+                        )
+                        .into(),
+                    ))]
+                }
+                Err(error) => return Err(error.context(format!("Reading {path}"))),
+            };
+
+            if self.data.language_label && image_mime(&entry.path).is_none() {
+                if let Some(name) = language_name(self.language(&entry.path, &globs)) {
+                    let label = format!(
+                        r#"<span class="mdbook-files-lang">{}</span>"#,
+                        html_escape(name)?
+                    );
+                    pane.insert(0, Event::Html(CowStr::Boxed(label.into())));
+                }
+            }
+
+            if self.data.lazy {
+                let mut html = String::new();
+                pulldown_cmark::html::push_html(&mut html, pane.into_iter());
+                let url = self.context.asset("html", html.as_bytes())?;
+                let url = html_escape_attr(url.as_str())?;
+                events.push(Event::Html(CowStr::Boxed(
+                    format!(
+                        r#"<div id="{prefix}file-{uuid}" class="{class} visible" {attributes} data-src="{url}"></div>"#
+                    )
+                    .into(),
+                )));
+                continue;
+            }
+
+            events.push(Event::Html(CowStr::Boxed(
+                format!(r#"<div id="{prefix}file-{uuid}" class="{class} visible" {attributes}>"#)
+                    .into(),
+            )));
+            events.extend(pane);
+            events.push(Event::Html(CowStr::Boxed("</div>".to_string().into())));
+        }
+
+        events.push(Event::Html(CowStr::Boxed("</div>".to_string().into())));
+        Ok(events)
+    }
+
+    pub(crate) fn events(&self) -> Result<Vec<Event<'static>>> {
+        let paths = self.files()?;
+        if paths.is_empty() && !self.data.allow_empty {
+            bail!("No files matched");
+        }
+
+        let mut events = vec![];
+
+        if let Some(title) = &self.data.title {
+            events.push(Event::Html(CowStr::Boxed(
+                format!(
+                    r#"<div class="mdbook-files-title">{}</div>"#,
+                    html_escape(title)?
+                )
+                .into(),
+            )));
+        }
+
+        let extra = split_classes(self.data.class.as_deref().unwrap_or_default())?;
+        if let Some(prefix) = &self.data.id_prefix {
+            validate_id_prefix(prefix)?;
+        }
+        if paths.is_empty() {
+            warn!("No files matched, showing placeholder");
+            let mut classes = vec!["mdbook-files", "mdbook-files-empty"];
+            classes.extend(&extra);
+            events.push(Event::Html(CowStr::Boxed(
+                format!(
+                    r#"<div id="{}" class="{}">(no files)</div>"#,
+                    self.id(),
+                    classes.join(" ")
+                )
+                .into(),
+            )));
+            if self.data.trailing_break {
+                events.push(Event::HardBreak);
+            }
+            return Ok(events);
+        }
+
+        // these are part of the list of files, which single mode does not have
+        if self.data.single && self.data.download_zip {
+            bail!("Single mode has no list of files to show the download_zip link in");
+        }
+        if self.data.single && self.data.root_header {
+            bail!("Single mode has no list of files to show the root_header above");
+        }
+        if self.data.single && paths.len() != 1 {
+            bail!(
+                "Single mode requires exactly one file, but {} matched",
+                paths.len()
+            );
+        }
+
+        // a single file takes up as much space as it needs, unless told otherwise
+        let height = match (&self.data.height, self.data.single) {
+            (Some(height), _) => Some(height.as_str()),
+            (None, false) => Some(self.context.default_height),
+            (None, true) => None,
+        };
+        let style = match height {
+            Some(height) => {
+                validate_height(height)?;
+                format!(r#" style="height: {height};""#)
+            }
+            None => String::new(),
+        };
+        #[cfg(not(unix))]
+        if self.data.show_mode {
+            static UNSUPPORTED: std::sync::Once = std::sync::Once::new();
+            UNSUPPORTED.call_once(|| {
+                warn!("show_mode has no effect, this platform has no permission bits")
+            });
+        }
+        let mut classes = vec![
+            "mdbook-files",
+            match self.data.single {
+                true => "mdbook-files-single",
+                false => self.data.layout.class(),
+            },
+        ];
+        if let Some(theme) = self.data.theme {
+            classes.push(theme.class());
+        }
+        classes.extend(&extra);
+        events.push(Event::Html(CowStr::Boxed(
+            format!(
+                r#"<div id="{}" class="{}"{style}>"#,
+                self.id(),
+                classes.join(" ")
+            )
+            .into(),
+        )));
+
+        let tree = match self.data.single {
+            true => None,
+            false => Some(self.tree(&paths)?),
+        };
+        let entries = match &tree {
+            Some(tree) => tree.files(&self.tree_options()),
+            None => paths.values().collect(),
+        };
+        let visible = self.visible(&entries)?;
+        if let Some(tree) = &tree {
+            events.push(Event::Html(CowStr::Boxed(self.left(tree, &paths)?.into())));
+        }
+        events.append(&mut self.right(&paths, visible)?);
+        events.push(Event::Html(CowStr::Boxed("</div>".to_string().into())));
+
+        // a single file has nothing to switch between, but may still need to be loaded
+        if tree.is_some() || self.data.lazy {
+            // variables of the script template, which are documented in the reference
+            let uuids: Vec<Uuid> = entries.iter().map(|entry| entry.uuid).collect();
+            let paths: Vec<&Utf8Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+            let mut context = tera::Context::new();
+            context.insert("id", &self.id());
+            context.insert("id_prefix", &self.id_prefix());
+            context.insert("uuids", &uuids);
+            context.insert("paths", &paths);
+            context.insert("count", &entries.len());
+            context.insert("visible", &visible.uuid);
+
+            let script = self
+                .context
+                .tera
+                .render("script", &context)
+                .with_context(|| {
+                    let paths: Vec<&str> = self
+                        .data
+                        .path
+                        .as_slice()
+                        .iter()
+                        .map(|path| path.as_str())
+                        .collect();
+                    format!(
+                        "Cannot render template \"script\" for files of {}",
+                        paths.join(", ")
+                    )
+                })?;
+
+            events.push(Event::Html(CowStr::Boxed(
+                format!("<script>{script}</script>").into(),
+            )));
+        }
+
+        if self.data.trailing_break {
+            events.push(Event::HardBreak);
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,754 @@
+use crate::{
+    config::Sort,
+    render::{html_escape, html_escape_attr},
+};
+use anyhow::{bail, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Serialize, Serializer};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// Options for rendering a [`TreeNode`]
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    /// Order of entries within each directory.
+    pub sort: Sort,
+
+    /// Render folders collapsed.
+    pub collapsed: bool,
+
+    /// Show the size of files.
+    pub show_size: bool,
+
+    /// Show the modification date of files.
+    pub show_modified: bool,
+
+    /// Mark executable files.
+    pub show_mode: bool,
+
+    /// Show icons for files and folders.
+    pub icons: bool,
+
+    /// Labels shown next to files, by their path.
+    pub annotations: BTreeMap<Utf8PathBuf, String>,
+
+    /// URL which the path of every file is appended to, to link to it.
+    pub link_base: Option<String>,
+
+    /// Prefix of the ids of files, including its separator.
+    pub id_prefix: String,
+
+    /// Paths of files or directories which are placed first in their directory, in this order.
+    pub priority: Vec<Utf8PathBuf>,
+}
+
+/// Tree of files, as shown in the left pane of a widget
+#[derive(Clone, Debug)]
+pub enum TreeNode {
+    Directory(BTreeMap<String, TreeNode>),
+    File(FileEntry),
+}
+
+/// Serialized form of a [`TreeNode`]
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SerializedNode<'a> {
+    Directory {
+        entries: &'a BTreeMap<String, TreeNode>,
+    },
+    File(&'a FileEntry),
+}
+
+/// Trees are serialized as objects with a `type` of either `directory` or `file`. Directories
+/// have their `entries` by name, files have the fields of their [`FileEntry`].
+impl Serialize for TreeNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TreeNode::Directory(entries) => SerializedNode::Directory { entries },
+            TreeNode::File(entry) => SerializedNode::File(entry),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Default for TreeNode {
+    fn default() -> Self {
+        TreeNode::Directory(Default::default())
+    }
+}
+
+impl TreeNode {
+    /// Build a tree from files, placing each at its path relative to `strip_prefix`.
+    ///
+    /// This can be used to render a tree outside of mdBook:
+    ///
+    /// ```
+    /// use camino::Utf8PathBuf;
+    /// use mdbook_files::{FileEntry, FilesMap, TreeNode, TreeOptions};
+    ///
+    /// let mut files = FilesMap::new();
+    /// for path in ["/project/Cargo.toml", "/project/src/main.rs"] {
+    ///     let entry = FileEntry::new(uuid::Uuid::new_v4(), path);
+    ///     files.insert(Utf8PathBuf::from(path), entry);
+    /// }
+    ///
+    /// let tree = TreeNode::from_paths(&files, "/project".into()).unwrap();
+    /// let html = tree.render(&TreeOptions::default()).unwrap();
+    /// assert!(html.contains("<span>src/</span>"));
+    /// assert!(html.contains(">main.rs</li>"));
+    /// ```
+    pub fn from_paths(paths: &FilesMap, strip_prefix: &Utf8Path) -> Result<TreeNode> {
+        let entries = paths
+            .iter()
+            .map(|(path, entry)| {
+                let relative = path
+                    .strip_prefix(strip_prefix)
+                    .with_context(|| format!("Path {path} is not inside {strip_prefix}"))?;
+                Ok((relative, entry))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_entries(entries)
+    }
+
+    /// Build a tree from files and the paths to place them at.
+    pub(crate) fn from_entries<'a>(
+        entries: impl IntoIterator<Item = (&'a Utf8Path, &'a FileEntry)>,
+    ) -> Result<TreeNode> {
+        let mut root = TreeNode::default();
+        for (path, entry) in entries {
+            let components: Vec<_> = path.components().map(|c| c.as_str()).collect();
+            root.insert(&components, entry.clone())
+                .with_context(|| format!("Cannot place {path} in tree"))?;
+        }
+        Ok(root)
+    }
+
+    fn insert(&mut self, path: &[&str], entry: FileEntry) -> Result<()> {
+        match (self, path) {
+            (TreeNode::Directory(files), [name]) => {
+                if files.contains_key(*name) {
+                    bail!("Entry {name} exists");
+                }
+                files.insert(name.to_string(), TreeNode::File(entry));
+            }
+            (TreeNode::Directory(files), [name, rest @ ..]) => {
+                files
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(rest, entry)?;
+            }
+            (TreeNode::Directory(_), []) => bail!("Path is empty"),
+            (TreeNode::File(_), _) => bail!("Entry is a file"),
+        }
+        Ok(())
+    }
+
+    /// Remove directories which do not contain any files, directly or in their subdirectories.
+    pub fn prune_empty(self) -> TreeNode {
+        match self {
+            TreeNode::File(entry) => TreeNode::File(entry),
+            TreeNode::Directory(files) => TreeNode::Directory(
+                files
+                    .into_iter()
+                    .map(|(name, node)| (name, node.prune_empty()))
+                    .filter(|(_, node)| !node.is_empty_directory())
+                    .collect(),
+            ),
+        }
+    }
+
+    fn is_empty_directory(&self) -> bool {
+        matches!(self, TreeNode::Directory(files) if files.is_empty())
+    }
+
+    /// Merge directories whose only entry is another directory into a single one, such as
+    /// `com/example/foo`. Directories containing files are kept as they are.
+    pub fn collapse_chains(self) -> TreeNode {
+        match self {
+            TreeNode::File(entry) => TreeNode::File(entry),
+            TreeNode::Directory(files) => TreeNode::Directory(
+                files
+                    .into_iter()
+                    .map(|(name, node)| node.collapse_chain(name))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Collapse the chain of directories starting at this one, returning its combined name.
+    fn collapse_chain(self, name: String) -> (String, TreeNode) {
+        match self {
+            TreeNode::Directory(files)
+                if files.len() == 1 && files.values().all(TreeNode::is_directory) =>
+            {
+                let (child, node) = files.into_iter().next().unwrap();
+                node.collapse_chain(format!("{name}/{child}"))
+            }
+            node => (name, node.collapse_chains()),
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        matches!(self, TreeNode::Directory(_))
+    }
+
+    /// Time the most recently modified file in this subtree was modified.
+    fn modified(&self) -> Option<SystemTime> {
+        match self {
+            TreeNode::File(entry) => entry.modified,
+            TreeNode::Directory(files) => files.values().filter_map(TreeNode::modified).max(),
+        }
+    }
+
+    /// Position of the first listed file in this subtree.
+    fn index(&self) -> Option<usize> {
+        match self {
+            TreeNode::File(entry) => entry.index,
+            TreeNode::Directory(files) => files.values().filter_map(TreeNode::index).min(),
+        }
+    }
+
+    /// Position of the first entry of `priority` which is a file in this subtree, or one of
+    /// their parents.
+    fn priority(&self, priority: &[Utf8PathBuf]) -> Option<usize> {
+        match self {
+            TreeNode::File(entry) => priority
+                .iter()
+                .position(|path| entry.path.starts_with(path)),
+            TreeNode::Directory(files) => files
+                .values()
+                .filter_map(|node| node.priority(priority))
+                .min(),
+        }
+    }
+
+    /// Entries of a directory, in the order given by `sort` with the ones given by `priority`
+    /// first, or the order of the explicit list of files if one was given.
+    fn sorted<'a>(
+        files: &'a BTreeMap<String, TreeNode>,
+        options: &TreeOptions,
+    ) -> Vec<(&'a str, &'a TreeNode)> {
+        let mut entries: Vec<_> = files
+            .iter()
+            .map(|(name, node)| (name.as_str(), node))
+            .collect();
+        match options.sort {
+            Sort::Name => {}
+            Sort::NameDirsFirst => {
+                entries.sort_by_key(|(_, node)| !node.is_directory());
+            }
+            Sort::Extension => {
+                entries.sort_by_key(|&(name, node)| match node {
+                    TreeNode::Directory(_) => "",
+                    TreeNode::File(_) => Utf8Path::new(name).extension().unwrap_or(""),
+                });
+            }
+            Sort::Modified => {
+                entries.sort_by_key(|(_, node)| Reverse(node.modified()));
+            }
+        }
+        if !options.priority.is_empty() {
+            entries.sort_by_key(|(_, node)| {
+                let priority = node.priority(&options.priority);
+                (priority.is_none(), priority)
+            });
+        }
+        // stable, so this has no effect unless files were given as a list
+        entries.sort_by_key(|(_, node)| node.index());
+        entries
+    }
+
+    /// Files in the tree, in the order in which they are rendered.
+    pub fn files(&self, options: &TreeOptions) -> Vec<&FileEntry> {
+        let mut output = vec![];
+        self.collect_files(&mut output, options);
+        output
+    }
+
+    fn collect_files<'a>(&'a self, output: &mut Vec<&'a FileEntry>, options: &TreeOptions) {
+        match self {
+            TreeNode::File(entry) => output.push(entry),
+            TreeNode::Directory(files) => {
+                for (_, node) in Self::sorted(files, options) {
+                    node.collect_files(output, options);
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, options: &TreeOptions) -> Result<String> {
+        let mut output = String::new();
+        match self {
+            TreeNode::File(_) => bail!("root node cannot be file"),
+            TreeNode::Directory(files) => Self::render_files(&mut output, files, "tree", options)?,
+        }
+        Ok(output)
+    }
+
+    fn render_files(
+        output: &mut dyn Write,
+        files: &BTreeMap<String, TreeNode>,
+        role: &str,
+        options: &TreeOptions,
+    ) -> Result<()> {
+        write!(output, r#"<ul role="{role}">"#)?;
+        for (path, node) in Self::sorted(files, options) {
+            node.render_inner(output, path, options)?;
+        }
+        write!(output, "</ul>")?;
+        Ok(())
+    }
+
+    fn render_inner(
+        &self,
+        output: &mut dyn Write,
+        name: &str,
+        options: &TreeOptions,
+    ) -> Result<()> {
+        match self {
+            TreeNode::File(entry) => {
+                let uuid = entry.uuid;
+                let prefix = &options.id_prefix;
+                let icon = match options.icons {
+                    true => icon(name),
+                    false => String::new(),
+                };
+                let name = html_escape(name)?;
+                let title = html_escape_attr(entry.path.as_str())?;
+                write!(
+                    output,
+                    r#"<li id="{prefix}button-{uuid}" class="mdbook-files-button" role="treeitem" tabindex="0" aria-selected="false" title="{title}">{icon}{name}"#
+                )?;
+                let executable = entry.mode.filter(|mode| mode & 0o111 != 0);
+                if let (true, Some(mode)) = (options.show_mode, executable) {
+                    write!(
+                        output,
+                        r#"<span class="mdbook-files-mode" title="Executable ({:o})">*</span>"#,
+                        mode & 0o7777
+                    )?;
+                }
+                if let Some(label) = options.annotations.get(&entry.path) {
+                    output.write_str(&badge(label)?)?;
+                }
+                if let Some(base) = &options.link_base {
+                    let href = html_escape_attr(&format!("{base}{}", url_path(&entry.path)))?;
+                    write!(
+                        output,
+                        r#"<a class="mdbook-files-link" href="{href}" target="_blank" rel="noopener" title="Open in a new tab">↗</a>"#
+                    )?;
+                }
+                if options.show_size {
+                    write!(
+                        output,
+                        r#"<span class="mdbook-files-size">{}</span>"#,
+                        human_size(entry.size)
+                    )?;
+                }
+                let modified = entry.modified.and_then(format_time);
+                if let (true, Some(time)) = (options.show_modified, modified) {
+                    write!(
+                        output,
+                        r#"<span class="mdbook-files-modified" title="{time}">{}</span>"#,
+                        &time[..10]
+                    )?;
+                }
+                write!(output, "</li>")?;
+            }
+            TreeNode::Directory(files) => {
+                let (class, expanded) = match options.collapsed {
+                    true => ("mdbook-files-folder mdbook-files-collapsed", false),
+                    false => ("mdbook-files-folder", true),
+                };
+                let icon = match options.icons {
+                    true => r#"<span class="mdbook-files-icon mdbook-files-icon-folder"></span>"#,
+                    false => "",
+                };
+                let name = html_escape(name)?;
+                write!(
+                    output,
+                    r#"<li class="{class}" role="treeitem" tabindex="0" aria-expanded="{expanded}"><span>{icon}{name}/</span>"#
+                )?;
+                Self::render_files(output, files, "group", options)?;
+                write!(output, "</li>")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// File found when walking a directory
+#[derive(Clone, Debug, Serialize)]
+pub struct FileEntry {
+    /// Identifier used for the file in the generated markup.
+    pub uuid: Uuid,
+
+    /// Path of the file, relative to the directory that was walked.
+    pub path: Utf8PathBuf,
+
+    /// Size of the file, in bytes.
+    pub size: u64,
+
+    /// Time the file was last modified, if the platform supports it.
+    #[serde(serialize_with = "serialize_time")]
+    pub modified: Option<SystemTime>,
+
+    /// Position of the file in the explicit list of files, if one was given.
+    pub index: Option<usize>,
+
+    /// Permission bits of the file, on platforms which have them.
+    pub mode: Option<u32>,
+}
+
+impl FileEntry {
+    /// Create an entry for a file with a given identifier, for example to build a tree by hand.
+    pub fn new(uuid: Uuid, path: impl Into<Utf8PathBuf>) -> Self {
+        FileEntry {
+            uuid,
+            path: path.into(),
+            size: 0,
+            modified: None,
+            index: None,
+            mode: None,
+        }
+    }
+}
+
+pub type FilesMap = BTreeMap<Utf8PathBuf, FileEntry>;
+
+/// File extensions which have their own icon in the stylesheet.
+const ICONS: &[&str] = &[
+    "c", "cpp", "css", "h", "hpp", "html", "jpg", "js", "json", "md", "png", "py", "rs", "sh",
+    "svg", "toml", "ts", "txt", "yaml", "yml",
+];
+
+/// Icon for a file, by extension. Files without a known extension get a generic icon.
+fn icon(name: &str) -> String {
+    let extension = Utf8Path::new(name)
+        .extension()
+        .map(str::to_ascii_lowercase)
+        .filter(|extension| ICONS.contains(&extension.as_str()))
+        .unwrap_or_else(|| "file".into());
+    format!(r#"<span class="mdbook-files-icon mdbook-files-icon-{extension}"></span>"#)
+}
+
+/// Render the label of an annotated file.
+pub(crate) fn badge(label: &str) -> Result<String> {
+    let label = html_escape(label)?;
+    Ok(format!(
+        r#"<span class="mdbook-files-badge">{label}</span>"#
+    ))
+}
+
+/// Percent-encode a relative path for use in a URL, keeping the separators.
+fn url_path(path: &Utf8Path) -> String {
+    let mut output = String::new();
+    for (index, component) in path.components().enumerate() {
+        if index > 0 {
+            output.push('/');
+        }
+        for byte in component.as_str().bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    output.push(byte as char)
+                }
+                _ => write!(output, "%{byte:02X}").unwrap(),
+            }
+        }
+    }
+    output
+}
+
+/// Format a size in bytes using binary units, such as `1.2 KiB`.
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{value:.1} {unit}")
+}
+
+/// Format a time as RFC 3339, if it lies between the years 1970 and 9999.
+pub(crate) fn format_time(time: SystemTime) -> Option<String> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (seconds < 253_402_300_800).then(|| humantime::format_rfc3339_seconds(time).to_string())
+}
+
+/// Serialize a modification time in the same format as it is shown in the tree.
+fn serialize_time<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    time.and_then(format_time).serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> FilesMap {
+        paths
+            .iter()
+            .map(|path| (Utf8PathBuf::from(path), FileEntry::new(Uuid::nil(), *path)))
+            .collect()
+    }
+
+    /// Build a tree from relative paths, numbering the files in order.
+    fn tree(paths: &[&str]) -> TreeNode {
+        let files: FilesMap = paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let uuid = Uuid::from_u128(index as u128 + 1);
+                (Utf8PathBuf::from(path), FileEntry::new(uuid, *path))
+            })
+            .collect();
+        TreeNode::from_paths(&files, "".into()).unwrap()
+    }
+
+    fn file(uuid: u128, path: &str, name: &str) -> String {
+        let uuid = Uuid::from_u128(uuid);
+        format!(
+            r#"<li id="button-{uuid}" class="mdbook-files-button" role="treeitem" tabindex="0" aria-selected="false" title="{path}">{name}</li>"#
+        )
+    }
+
+    fn folder(name: &str, children: &str) -> String {
+        format!(
+            r#"<li class="mdbook-files-folder" role="treeitem" tabindex="0" aria-expanded="true"><span>{name}/</span><ul role="group">{children}</ul></li>"#
+        )
+    }
+
+    #[test]
+    fn render_file() {
+        let html = tree(&["README.md"])
+            .render(&TreeOptions::default())
+            .unwrap();
+        let expected = format!(
+            r#"<ul role="tree">{}</ul>"#,
+            file(1, "README.md", "README.md")
+        );
+        assert_eq!(html, expected);
+    }
+
+    #[test]
+    fn render_nested_folder() {
+        let html = tree(&["src/bin/main.rs"])
+            .render(&TreeOptions::default())
+            .unwrap();
+        let main = file(1, "src/bin/main.rs", "main.rs");
+        let expected = format!(
+            r#"<ul role="tree">{}</ul>"#,
+            folder("src", &folder("bin", &main))
+        );
+        assert_eq!(html, expected);
+    }
+
+    #[test]
+    fn render_folder_with_two_files() {
+        let html = tree(&["src/lib.rs", "src/main.rs"])
+            .render(&TreeOptions::default())
+            .unwrap();
+        let files = file(1, "src/lib.rs", "lib.rs") + &file(2, "src/main.rs", "main.rs");
+        let expected = format!(r#"<ul role="tree">{}</ul>"#, folder("src", &files));
+        assert_eq!(html, expected);
+    }
+
+    #[test]
+    fn render_collapsed_folder() {
+        let options = TreeOptions {
+            collapsed: true,
+            ..Default::default()
+        };
+        let html = tree(&["src/main.rs"]).render(&options).unwrap();
+        let main = file(1, "src/main.rs", "main.rs");
+        let expected = format!(
+            r#"<ul role="tree"><li class="mdbook-files-folder mdbook-files-collapsed" role="treeitem" tabindex="0" aria-expanded="false"><span>src/</span><ul role="group">{main}</ul></li></ul>"#
+        );
+        assert_eq!(html, expected);
+    }
+
+    #[test]
+    fn render_link() {
+        let options = TreeOptions {
+            link_base: Some("https://example.com/blob/main/".into()),
+            ..Default::default()
+        };
+        let html = tree(&["src/say \"hi\".rs"]).render(&options).unwrap();
+        assert!(html.contains(
+            r#"<a class="mdbook-files-link" href="https://example.com/blob/main/src/say%20%22hi%22.rs" target="_blank" rel="noopener" title="Open in a new tab">↗</a></li>"#
+        ));
+    }
+
+    #[test]
+    fn render_escapes_names() {
+        let html = tree(&["a<b>.txt"]).render(&TreeOptions::default()).unwrap();
+        assert!(html.contains(r#"title="a&lt;b&gt;.txt">a&lt;b&gt;.txt</li>"#));
+    }
+
+    #[test]
+    fn from_paths_nests_directories() {
+        let files = files(&["/project/Cargo.toml", "/project/src/main.rs"]);
+        let tree = TreeNode::from_paths(&files, "/project".into()).unwrap();
+        let TreeNode::Directory(root) = &tree else {
+            panic!("root is not a directory");
+        };
+        assert!(matches!(root["Cargo.toml"], TreeNode::File(_)));
+        let TreeNode::Directory(src) = &root["src"] else {
+            panic!("src is not a directory");
+        };
+        assert!(matches!(src["main.rs"], TreeNode::File(_)));
+    }
+
+    #[test]
+    fn render_files_map_by_relative_paths() {
+        // files are keyed by their full path, but placed and titled by their relative one
+        let files: FilesMap = [
+            ("/project/docs/README.md", "README.md", 1),
+            ("/project/docs/src/main.rs", "src/main.rs", 2),
+        ]
+        .into_iter()
+        .map(|(full, path, uuid)| {
+            let entry = FileEntry::new(Uuid::from_u128(uuid), path);
+            (Utf8PathBuf::from(full), entry)
+        })
+        .collect();
+        let tree =
+            TreeNode::from_entries(files.values().map(|entry| (entry.path.as_path(), entry)))
+                .unwrap();
+        let html = tree.render(&TreeOptions::default()).unwrap();
+        let expected = [
+            file(1, "README.md", "README.md"),
+            folder("src", &file(2, "src/main.rs", "main.rs")),
+        ]
+        .concat();
+        assert_eq!(html, format!(r#"<ul role="tree">{expected}</ul>"#));
+    }
+
+    #[test]
+    fn from_paths_rejects_files_outside_prefix() {
+        let files = files(&["/project/Cargo.toml", "/other/main.rs"]);
+        assert!(TreeNode::from_paths(&files, "/project".into()).is_err());
+    }
+
+    #[test]
+    fn serialize_tree() {
+        let tree = tree(&["src/main.rs"]);
+        let json = serde_json::to_value(&tree).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "directory",
+                "entries": {
+                    "src": {
+                        "type": "directory",
+                        "entries": {
+                            "main.rs": {
+                                "type": "file",
+                                "uuid": Uuid::from_u128(1),
+                                "path": "src/main.rs",
+                                "size": 0,
+                                "modified": null,
+                                "index": null,
+                                "mode": null,
+                            },
+                        },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn collapse_chains() {
+        let tree = tree(&["com/example/foo/Bar.java", "com/example/foo/Baz.java"]);
+        let html = tree
+            .collapse_chains()
+            .render(&TreeOptions::default())
+            .unwrap();
+        let expected = folder(
+            "com/example/foo",
+            &[
+                file(1, "com/example/foo/Bar.java", "Bar.java"),
+                file(2, "com/example/foo/Baz.java", "Baz.java"),
+            ]
+            .concat(),
+        );
+        assert_eq!(html, format!(r#"<ul role="tree">{expected}</ul>"#));
+    }
+
+    #[test]
+    fn collapse_chains_stops_at_files() {
+        let tree = tree(&["a/b/c/x.rs", "a/y.rs"]);
+        let html = tree
+            .collapse_chains()
+            .render(&TreeOptions::default())
+            .unwrap();
+        let expected = folder(
+            "a",
+            &[
+                folder("b/c", &file(1, "a/b/c/x.rs", "x.rs")),
+                file(2, "a/y.rs", "y.rs"),
+            ]
+            .concat(),
+        );
+        assert_eq!(html, format!(r#"<ul role="tree">{expected}</ul>"#));
+    }
+
+    #[test]
+    fn prune_empty_directories() {
+        let mut tree = tree(&["src/main.rs"]);
+        if let TreeNode::Directory(files) = &mut tree {
+            let nested = TreeNode::Directory([("nested".into(), TreeNode::default())].into());
+            files.insert("empty".into(), nested);
+        }
+        let options = TreeOptions::default();
+        assert!(tree.render(&options).unwrap().contains("empty"));
+        let html = tree.prune_empty().render(&options).unwrap();
+        let expected = folder("src", &file(1, "src/main.rs", "main.rs"));
+        assert_eq!(html, format!(r#"<ul role="tree">{expected}</ul>"#));
+    }
+
+    #[test]
+    fn priority_comes_first() {
+        let tree = tree(&[
+            "Cargo.lock",
+            "Cargo.toml",
+            "src/lib.rs",
+            "src/main.rs",
+            "tests/main.rs",
+        ]);
+        let options = TreeOptions {
+            priority: vec!["Cargo.toml".into(), "src/main.rs".into()],
+            ..Default::default()
+        };
+        let paths: Vec<_> = tree
+            .files(&options)
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "Cargo.toml",
+                "src/main.rs",
+                "src/lib.rs",
+                "Cargo.lock",
+                "tests/main.rs"
+            ]
+        );
+    }
+}
@@ -0,0 +1,615 @@
+use crate::{
+    config::{Files, Paths},
+    preprocessor::Context,
+    render::Instance,
+    tree::{FileEntry, FilesMap},
+};
+use anyhow::{bail, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{
+    overrides::{Override, OverrideBuilder},
+    types::{Types, TypesBuilder},
+    WalkBuilder,
+};
+use log::*;
+use std::{collections::BTreeMap, fs::Metadata, io::Read, path::Path, time::Instant};
+use uuid::Uuid;
+
+/// Find the files that a widget with the given options shows, without rendering anything.
+///
+/// Paths of the options are relative to `prefix`, which files may not escape. Identifiers of the
+/// files are deterministic.
+pub fn discover(config: &Files, prefix: &Utf8Path) -> Result<FilesMap> {
+    Context::detached(prefix, |context| {
+        Instance {
+            context,
+            data: config.clone(),
+            uuid: Uuid::nil(),
+        }
+        .files()
+    })
+}
+
+/// Options which determine the files found for a widget, used to reuse listings.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ListingKey {
+    prefix: Option<Utf8PathBuf>,
+    path: Paths,
+    list: Option<Vec<Utf8PathBuf>>,
+    relative_to: Option<Utf8PathBuf>,
+    files: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    skip_marker: Option<String>,
+    ignore_case_insensitive: bool,
+    same_file_system: bool,
+    types: Vec<String>,
+    types_not: Vec<String>,
+    hidden: bool,
+    follow_links: bool,
+    dot_ignore: bool,
+    custom_ignore_filename: Option<String>,
+    git_global: bool,
+    git_exclude: bool,
+    git_ignore: bool,
+    require_git: bool,
+    git_ignore_parents: bool,
+    max_depth: Option<usize>,
+    max_filesize: Option<u64>,
+    max_files: Option<usize>,
+}
+
+/// Globs of the `image` file type, which are the images that are shown inline.
+const IMAGE_GLOBS: &[&str] = &["*.png", "*.jpg", "*.jpeg", "*.gif", "*.svg", "*.webp"];
+
+/// Number of bytes at the start of each file that are searched for the skip marker.
+const SKIP_MARKER_SCAN: u64 = 4096;
+
+/// Determine if the start of a file contains the marker.
+fn contains_marker(path: &Utf8Path, marker: &str) -> Result<bool> {
+    if marker.is_empty() {
+        bail!("skip_marker must not be empty");
+    }
+    let mut prefix = vec![];
+    std::fs::File::open(path)?
+        .take(SKIP_MARKER_SCAN)
+        .read_to_end(&mut prefix)?;
+    Ok(prefix
+        .windows(marker.len())
+        .any(|window| window == marker.as_bytes()))
+}
+
+/// Permission bits of a file, on platforms which have them.
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Determine if an error of the walker is caused by a symbolic link pointing to its ancestor.
+fn is_loop(error: &ignore::Error) -> bool {
+    match error {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+impl<'a> Instance<'a> {
+    /// Key under which the files of this widget are cached.
+    fn listing_key(&self) -> ListingKey {
+        let data = &self.data;
+        ListingKey {
+            prefix: data.prefix.clone(),
+            path: data.path.clone(),
+            list: data.list.clone(),
+            relative_to: data.relative_to.clone(),
+            files: data.files.clone(),
+            include: data.include.clone(),
+            exclude: data.exclude.clone(),
+            skip_marker: data.skip_marker.clone(),
+            ignore_case_insensitive: data.ignore_case_insensitive,
+            same_file_system: data.same_file_system,
+            types: data.types.clone(),
+            types_not: data.types_not.clone(),
+            hidden: data.hidden,
+            follow_links: data.follow_links,
+            dot_ignore: data.dot_ignore,
+            custom_ignore_filename: data.custom_ignore_filename.clone(),
+            git_global: data.git_global,
+            git_exclude: data.git_exclude,
+            git_ignore: data.git_ignore,
+            require_git: data.require_git,
+            git_ignore_parents: data.git_ignore_parents,
+            max_depth: data.max_depth,
+            max_filesize: data.max_filesize,
+            max_files: data.max_files,
+        }
+    }
+
+    /// Files of this widget, reusing the listing of an earlier widget with the same options.
+    pub(crate) fn files(&self) -> Result<FilesMap> {
+        let key = self.listing_key();
+        let cached = self.context.listings.lock().unwrap().get(&key).cloned();
+        let mut paths = match cached {
+            Some(paths) => {
+                debug!("Reusing listing of {} files", paths.len());
+                paths
+            }
+            None => {
+                let paths = self.find()?;
+                self.context
+                    .listings
+                    .lock()
+                    .unwrap()
+                    .insert(key, paths.clone());
+                paths
+            }
+        };
+
+        // identifiers are unique to every widget
+        for entry in paths.values_mut() {
+            entry.uuid = self.file_uuid(&entry.path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Find the files of this widget.
+    fn find(&self) -> Result<FilesMap> {
+        if self.data.prefix.is_some() {
+            let prefix = self.prefix();
+            if !prefix.is_dir() {
+                bail!("Prefix {prefix} does not exist or is not a directory");
+            }
+        }
+
+        for parent in self.parents() {
+            if !parent.exists() {
+                bail!("Directory does not exist: {parent}");
+            }
+            if !parent.is_dir() {
+                bail!("Path is not a directory: {parent}");
+            }
+            self.check_inside(&parent)?;
+        }
+
+        let mut paths: FilesMap = Default::default();
+        match &self.data.list {
+            Some(list) => self.listed(list, &mut paths)?,
+            None => {
+                for parent in self.parents() {
+                    self.walk(&parent, &mut paths)?;
+                }
+            }
+        }
+
+        self.check_max_files(&paths)?;
+        info!("Found {} matching files", paths.len());
+        Ok(paths)
+    }
+
+    /// Make sure that no more than `max_files` files were found.
+    fn check_max_files(&self, paths: &FilesMap) -> Result<()> {
+        if let Some(max_files) = self.data.max_files {
+            if paths.len() > max_files {
+                bail!(
+                    "Matched at least {} files, which exceeds max_files = {max_files}",
+                    paths.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure that a path does not escape the prefix of the book, unless this is allowed.
+    fn check_inside(&self, path: &Utf8Path) -> Result<()> {
+        if self.context.allow_outside {
+            return Ok(());
+        }
+        let prefix = self
+            .context
+            .prefix
+            .canonicalize_utf8()
+            .with_context(|| format!("Cannot resolve prefix {}", self.context.prefix))?;
+        let canonical = path.canonicalize_utf8()?;
+        if !canonical.starts_with(&prefix) {
+            bail!("Path {path} is outside of the prefix {prefix}, set allow_outside to allow this");
+        }
+        Ok(())
+    }
+
+    /// Path that files found in `parent` are shown relative to.
+    fn base(&self, parent: &Utf8Path) -> Utf8PathBuf {
+        match &self.data.relative_to {
+            Some(path) => self.prefix().join(path),
+            None => parent.to_owned(),
+        }
+    }
+
+    /// Maximum depth to walk `parent` to, so that `max_depth` counts from the path that files are
+    /// shown relative to. A depth of 1 only finds the files directly inside of it.
+    fn max_depth(&self, parent: &Utf8Path) -> Option<usize> {
+        let offset = parent
+            .strip_prefix(self.base(parent))
+            .map_or(0, |path| path.components().count());
+        self.data
+            .max_depth
+            .map(|depth| depth.saturating_sub(offset))
+    }
+
+    /// Add an explicit list of files, keeping their order.
+    fn listed(&self, list: &[Utf8PathBuf], paths: &mut FilesMap) -> Result<()> {
+        let [parent] = &self.parents()[..] else {
+            bail!("An explicit list of files requires a single path");
+        };
+        let base = self.base(parent);
+        for (index, file) in list.iter().enumerate() {
+            let full = parent.join(file);
+            if !full.is_file() {
+                bail!("Listed file {full} does not exist");
+            }
+            self.check_inside(&full)?;
+            let relative = full
+                .strip_prefix(&base)
+                .with_context(|| format!("File {full} is not inside of {base}"))?
+                .to_owned();
+            let metadata = full.metadata()?;
+            let entry = FileEntry {
+                uuid: self.file_uuid(&relative),
+                path: relative,
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                index: Some(index),
+                mode: mode(&metadata),
+            };
+            if paths.insert(full, entry).is_some() {
+                bail!("File {file} is listed more than once");
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk a directory and add the files found in it, relative to it.
+    fn walk(&self, parent: &Utf8Path, paths: &mut FilesMap) -> Result<()> {
+        let base = self.base(parent);
+        let mut relatives: BTreeMap<_, _> = paths
+            .iter()
+            .map(|(full, entry)| (entry.path.clone(), full.clone()))
+            .collect();
+        let mut overrides = OverrideBuilder::new(parent);
+        for item in &self.data.files {
+            overrides.add(item)?;
+        }
+        for item in &self.data.exclude {
+            if item.starts_with('!') {
+                bail!("Exclude glob {item:?} must not start with `!`");
+            }
+            overrides.add(&format!("!{item}"))?;
+        }
+        let overrides = overrides.build()?;
+        let include = self.include()?;
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        for glob in IMAGE_GLOBS {
+            types.add("image", glob)?;
+        }
+        for name in &self.data.types {
+            types.select(name);
+        }
+        for name in &self.data.types_not {
+            types.negate(name);
+        }
+        let types = types.build()?;
+        let start = Instant::now();
+        let (mut visited, mut skipped) = (0, 0);
+        let found = paths.len();
+        // symbolic links can make the same file show up under several paths
+        let mut canonicals: BTreeMap<Utf8PathBuf, Utf8PathBuf> = BTreeMap::new();
+        let mut walker = WalkBuilder::new(parent);
+        walker
+            .standard_filters(false)
+            .ignore_case_insensitive(self.data.ignore_case_insensitive)
+            .same_file_system(self.data.same_file_system)
+            .require_git(self.data.require_git)
+            .hidden(self.data.hidden)
+            .ignore(self.data.dot_ignore)
+            .git_ignore(self.data.git_ignore)
+            .git_exclude(self.data.git_exclude)
+            .git_global(self.data.git_global)
+            .parents(self.data.git_ignore_parents)
+            .follow_links(self.data.follow_links)
+            .max_depth(self.max_depth(parent))
+            .overrides(overrides.clone())
+            .types(types.clone())
+            .max_filesize(self.data.max_filesize);
+        if let Some(name) = &self.data.custom_ignore_filename {
+            walker.add_custom_ignore_filename(name);
+        }
+
+        let walker = walker.build();
+
+        for path in walker {
+            let path = match path {
+                Ok(path) => path,
+                Err(error) if is_loop(&error) => {
+                    warn!("Not following symbolic link: {error}");
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            visited += 1;
+            let custom_ignore = self.data.custom_ignore_filename.as_deref();
+            if custom_ignore.is_some_and(|name| path.file_name() == name) {
+                continue;
+            }
+            if path.file_type().unwrap().is_file() {
+                let Some(full) = Utf8Path::from_path(path.path()).map(Utf8Path::to_owned) else {
+                    warn!(
+                        "Skipping {}, its path is not valid UTF-8",
+                        path.path().display()
+                    );
+                    continue;
+                };
+                if !is_included(include.as_ref(), full.strip_prefix(parent)?) {
+                    continue;
+                }
+                if self.data.follow_links {
+                    // prefer the file itself over links to it, then the first path in order
+                    let canonical = full.canonicalize_utf8()?;
+                    let rank = |path: &Utf8Path| (path != canonical, path.to_owned());
+                    match canonicals.get(&canonical) {
+                        Some(other) if rank(other) < rank(&full) => {
+                            debug!("Skipping {full}, it is the same file as {other}");
+                            continue;
+                        }
+                        Some(other) => {
+                            debug!("Skipping {other}, it is the same file as {full}");
+                            if let Some(entry) = paths.remove(other) {
+                                relatives.remove(&entry.path);
+                            }
+                        }
+                        None => {}
+                    }
+                    canonicals.insert(canonical, full.clone());
+                }
+                if let Some(marker) = &self.data.skip_marker {
+                    if contains_marker(&full, marker)? {
+                        debug!("Skipping {full}, it contains the skip marker");
+                        skipped += 1;
+                        continue;
+                    }
+                }
+
+                let relative = full
+                    .strip_prefix(&base)
+                    .with_context(|| format!("File {full} is not inside of {base}"))?
+                    .to_owned();
+                if let Some(other) = relatives.insert(relative.clone(), full.clone()) {
+                    bail!("Files {other} and {full} would both be shown as {relative}");
+                }
+                let metadata = path.metadata()?;
+                let entry = FileEntry {
+                    uuid: self.file_uuid(&relative),
+                    path: relative,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                    index: None,
+                    mode: mode(&metadata),
+                };
+                paths.insert(full, entry);
+            }
+
+            // stop early, rather than walking all of a large directory first
+            self.check_max_files(paths)
+                .with_context(|| format!("Walking {parent}"))?;
+        }
+
+        info!(
+            "Walked {parent} in {:?}, visiting {visited} entries and matching {} files",
+            start.elapsed(),
+            paths.len() - found
+        );
+        if log_enabled!(Level::Debug) {
+            self.walk_stats(parent, &overrides, &types, include.as_ref(), paths, skipped)?;
+        }
+
+        Ok(())
+    }
+
+    /// Matcher of the include globs, which narrow down the files matched by everything else.
+    ///
+    /// Globs follow the syntax of ignore files, so ones without a slash match names at any depth
+    /// and ones with a slash are relative to the walked directory.
+    fn include(&self) -> Result<Option<GlobSet>> {
+        if self.data.include.is_empty() {
+            return Ok(None);
+        }
+        let mut set = GlobSetBuilder::new();
+        for item in &self.data.include {
+            if item.starts_with('!') {
+                bail!("Include glob {item:?} must not start with `!`, use exclude instead");
+            }
+            let trimmed = item.trim_end_matches('/');
+            let pattern = match trimmed.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if trimmed.contains('/') => trimmed.to_string(),
+                None => format!("**/{trimmed}"),
+            };
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid include glob {item:?}"))?;
+            set.add(glob);
+        }
+        Ok(Some(set.build()?))
+    }
+
+    /// Log why the files of a directory which were not matched were filtered out.
+    ///
+    /// This walks the directory again without any filters, so it is only done when debugging.
+    fn walk_stats(
+        &self,
+        parent: &Utf8Path,
+        overrides: &Override,
+        types: &Types,
+        include: Option<&GlobSet>,
+        paths: &FilesMap,
+        skipped: usize,
+    ) -> Result<()> {
+        // the walker skips ignored directories entirely, so their files count as ignored too
+        let overridden = |path: &Path| {
+            path.ancestors()
+                .take_while(|ancestor| *ancestor != parent)
+                .enumerate()
+                .any(|(index, ancestor)| overrides.matched(ancestor, index > 0).is_ignore())
+        };
+        let (mut by_overrides, mut by_include, mut by_hidden, mut by_types, mut by_size) =
+            (0, 0, 0, 0, 0);
+        let mut by_files = 0usize;
+        let walker = WalkBuilder::new(parent)
+            .standard_filters(false)
+            .follow_links(self.data.follow_links)
+            .max_depth(self.max_depth(parent))
+            .build();
+        for path in walker {
+            let path = match path {
+                Ok(path) => path,
+                Err(error) if is_loop(&error) => continue,
+                Err(error) => return Err(error.into()),
+            };
+            if !path.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            // files with paths which are not valid UTF-8 are skipped, and already logged
+            let Some(full) = Utf8Path::from_path(path.path()) else {
+                continue;
+            };
+            if paths.contains_key(full) {
+                continue;
+            }
+            let relative = full.strip_prefix(parent)?;
+            let hidden = relative.components().any(|c| c.as_str().starts_with('.'));
+            let size = path.metadata()?.len();
+            if overridden(path.path()) {
+                by_overrides += 1;
+            } else if !is_included(include, relative) {
+                by_include += 1;
+            } else if self.data.hidden && hidden {
+                by_hidden += 1;
+            } else if types.matched(path.path(), false).is_ignore() {
+                by_types += 1;
+            } else if self.data.max_filesize.is_some_and(|max| size > max) {
+                by_size += 1;
+            } else {
+                by_files += 1;
+            }
+        }
+        debug!(
+            "Filtered out of {parent}: {by_overrides} by files or exclude, \
+            {by_include} by include, {by_hidden} hidden, {by_types} by types, {by_size} by size, \
+            {skipped} by the skip marker, {} by ignore files",
+            by_files.saturating_sub(skipped)
+        );
+        Ok(())
+    }
+}
+
+/// Determine if a file, relative to the walked directory, matches the include globs if there are
+/// any. Like in ignore files, matching one of its parent directories is enough.
+fn is_included(include: Option<&GlobSet>, relative: &Utf8Path) -> bool {
+    include.is_none_or(|include| {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_str().is_empty())
+            .any(|ancestor| include.is_match(ancestor))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Relative paths of the files found with the given options, over the fixtures.
+    fn discover_paths(options: &str) -> Vec<String> {
+        let prefix = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/fixtures");
+        let config: Files = toml::from_str(options).unwrap();
+        let files = discover(&config, &prefix).unwrap();
+        files.values().map(|entry| entry.path.to_string()).collect()
+    }
+
+    #[test]
+    fn include_narrows_files() {
+        let paths = discover_paths(
+            r#"
+            path = "flatten"
+            files = ["*.md", "*.rs"]
+            include = ["src/**"]
+            "#,
+        );
+        assert_eq!(paths, ["src/main.rs", "src/util/mod.rs"]);
+
+        // files hidden by `files` are not brought back by `include`
+        let paths = discover_paths(
+            r#"
+            path = "flatten"
+            files = ["!*.md"]
+            include = ["README.md", "main.rs"]
+            "#,
+        );
+        assert_eq!(paths, ["src/main.rs", "tests/main.rs"]);
+
+        let paths = discover_paths(
+            r#"
+            path = "flatten"
+            include = ["/src/util/"]
+            exclude = ["tests/"]
+            "#,
+        );
+        assert_eq!(paths, ["src/util/mod.rs"]);
+    }
+
+    #[test]
+    fn max_files_applies_to_lists() {
+        let prefix = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/fixtures");
+        let config: Files = toml::from_str(
+            r#"
+            path = "flatten"
+            list = ["README.md", "src/main.rs"]
+            max_files = 1
+            "#,
+        )
+        .unwrap();
+        let error = format!("{:#}", discover(&config, &prefix).unwrap_err());
+        assert!(error.contains("exceeds max_files = 1"), "{error}");
+
+        let config: Files = toml::from_str("path = \"flatten\"\nmax_files = 2").unwrap();
+        let error = format!("{:#}", discover(&config, &prefix).unwrap_err());
+        assert!(error.contains("exceeds max_files = 2"), "{error}");
+    }
+
+    #[test]
+    fn discover_fixture() {
+        let prefix = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/fixtures");
+        let config: Files = toml::from_str("path = \"flatten\"").unwrap();
+        let files = discover(&config, &prefix).unwrap();
+        let paths: Vec<_> = files.values().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "README.md",
+                "src/main.rs",
+                "src/util/mod.rs",
+                "tests/main.rs"
+            ]
+        );
+    }
+}